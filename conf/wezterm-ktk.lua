@@ -0,0 +1,58 @@
+-- Tab colors of ktk, generated by `ktk wezterm-lua`.
+--
+-- ktk publishes the colors of the cluster of each tab in the user variables
+-- ktk_active_bg, ktk_active_fg, ktk_inactive_bg and ktk_inactive_fg of its
+-- pane. Load this file from wezterm.lua with `require("ktk")`.
+local wezterm = require("wezterm")
+
+local function ktk_vars(tab)
+	local vars = tab.active_pane.user_vars
+	if vars.ktk_active_bg ~= nil then
+		return vars
+	end
+	-- The active pane may be a split of the pane opened by ktk
+	local mux_tab = wezterm.mux.get_tab(tab.tab_id)
+	if mux_tab == nil then
+		return vars
+	end
+	for _, pane in ipairs(mux_tab:panes()) do
+		local pane_vars = pane:get_user_vars()
+		if pane_vars.ktk_active_bg ~= nil then
+			return pane_vars
+		end
+	end
+	return vars
+end
+
+local function ktk_color(vars, name)
+	local value = vars["ktk_" .. name]
+	if value == nil or value == "" or value == "NONE" then
+		return nil
+	end
+	return value
+end
+
+wezterm.on("format-tab-title", function(tab, tabs, panes, config, hover, max_width)
+	local title = tab.tab_title
+	if title == nil or title == "" then
+		title = tab.active_pane.title
+	end
+	title = wezterm.truncate_right(" " .. title .. " ", max_width)
+
+	local vars = ktk_vars(tab)
+	local state = tab.is_active and "active" or "inactive"
+	local bg = ktk_color(vars, state .. "_bg")
+	local fg = ktk_color(vars, state .. "_fg")
+	if bg == nil and fg == nil then
+		return title
+	end
+	local cells = {}
+	if bg ~= nil then
+		table.insert(cells, { Background = { Color = bg } })
+	end
+	if fg ~= nil then
+		table.insert(cells, { Foreground = { Color = fg } })
+	end
+	table.insert(cells, { Text = title })
+	return cells
+end)
@@ -0,0 +1,231 @@
+//! Namespaces cache, with the result of the last scan of each cluster
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cache {
+    #[serde(default)]
+    pub clusters: BTreeMap<String, ClusterCache>,
+}
+
+/// Lock held while the cache is refreshed. It is an advisory lock on the
+/// lock file, released when it is dropped or when ktk is killed.
+#[derive(Debug)]
+pub struct CacheLock {
+    _file: File,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClusterCache {
+    pub last_success: Option<u64>, // time of the last successful scan
+    pub last_attempt: u64,         // time of the last scan
+    pub namespaces: Vec<String>,   // namespaces found by the last successful scan
+    pub error: Option<String>,     // error of the last scan if it failed
+    #[serde(default)]
+    pub scan_hash: u64, // hash of the settings of the cluster used by the last scan
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl ClusterCache {
+    // The namespaces come from an older scan, the last one failed
+    pub fn is_stale(&self) -> bool {
+        self.error.is_some()
+    }
+
+    // The cluster must be scanned again if the last attempt is older than
+    // maxage seconds or if its settings have changed since
+    pub fn is_outdated(&self, maxage: u64, scan_hash: u64) -> bool {
+        let now = now();
+        now.saturating_sub(self.last_attempt) > maxage || self.scan_hash != scan_hash
+    }
+}
+
+impl CacheLock {
+    fn lockfile(cache: &str) -> String {
+        format!("{cache}.lock")
+    }
+
+    fn open(cache: &str) -> Option<File> {
+        let path = CacheLock::lockfile(cache);
+        if let Some(parent) = Path::new(&path).parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        match OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+        {
+            Ok(f) => Some(f),
+            Err(e) => {
+                warn!("Couldn't open lock {path}: {e}");
+                None
+            }
+        }
+    }
+
+    // A refresh of the cache is in progress
+    pub fn is_locked(cache: &str) -> bool {
+        let Ok(file) = File::open(CacheLock::lockfile(cache)) else {
+            return false;
+        };
+        matches!(file.try_lock_shared(), Err(TryLockError::WouldBlock))
+    }
+
+    // Lock the cache, unless another ktk is refreshing it
+    pub fn acquire(cache: &str) -> Option<CacheLock> {
+        let file = CacheLock::open(cache)?;
+        match file.try_lock() {
+            Ok(()) => Some(CacheLock { _file: file }),
+            Err(_) => None,
+        }
+    }
+
+    // Lock the cache, after the end of the refresh in progress if any
+    pub fn wait(cache: &str) -> Option<CacheLock> {
+        let file = CacheLock::open(cache)?;
+        match file.lock() {
+            Ok(()) => Some(CacheLock { _file: file }),
+            Err(e) => {
+                warn!("Couldn't lock cache {cache}: {e}");
+                None
+            }
+        }
+    }
+}
+
+impl Cache {
+    pub fn read(path: &str) -> Cache {
+        let content = match fs::read(path) {
+            Ok(v) => v,
+            Err(_) => return Cache::default(),
+        };
+        match serde_json::from_slice(&content) {
+            Ok(v) => v,
+            Err(e) => {
+                info!("cache {path} is ignored, it will be rebuilt: {e}");
+                Cache::default()
+            }
+        }
+    }
+
+    pub fn write(&self, path: &str) {
+        // Write in a temporary file first, so that a reader never sees a
+        // partially written cache
+        let tmp = format!("{path}.{}.tmp", std::process::id());
+        let content = serde_json::to_vec(self).expect("Couldn't serialize cache");
+        if let Err(e) = fs::write(&tmp, content).and_then(|_| fs::rename(&tmp, path)) {
+            warn!("Couldn't write cache {path}: {e}");
+        }
+    }
+
+    // Record the result of the scan of a cluster, the namespaces of the
+    // previous successful scan are kept if it failed
+    pub fn update(&mut self, cluster: &str, scan_hash: u64, result: Result<Vec<String>, String>) {
+        let entry = self.clusters.entry(cluster.to_string()).or_default();
+        let now = now();
+        entry.last_attempt = now;
+        entry.scan_hash = scan_hash;
+        match result {
+            Ok(mut namespaces) => {
+                namespaces.sort();
+                entry.namespaces = namespaces;
+                entry.last_success = Some(now);
+                entry.error = None;
+            }
+            Err(e) => entry.error = Some(e),
+        }
+    }
+
+    // Returns the sorted list of `namespace<sep>cluster` for the clusters given
+    pub fn entries(&self, clusters: &[String], sep: &str) -> Vec<String> {
+        let mut result: Vec<String> = self
+            .clusters
+            .iter()
+            .filter(|(name, _)| clusters.contains(name))
+            .flat_map(|(name, c)| {
+                c.namespaces
+                    .iter()
+                    .map(move |ns| format!("{ns}{sep}{name}"))
+            })
+            .collect();
+        result.sort();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cache, CacheLock, ClusterCache, now};
+
+    #[test]
+    fn test_update_keeps_namespaces_on_error() {
+        let mut cache = Cache::default();
+        cache.update("prod", 0, Ok(vec!["b".to_string(), "a".to_string()]));
+        cache.update("prod", 0, Err("prod is unreachable".to_string()));
+        let prod = &cache.clusters["prod"];
+        assert_eq!(prod.namespaces, vec!["a", "b"]);
+        assert!(prod.is_stale());
+        assert!(prod.last_success.is_some());
+        cache.update("prod", 0, Ok(vec!["c".to_string()]));
+        assert!(!cache.clusters["prod"].is_stale());
+    }
+
+    #[test]
+    fn test_entries() {
+        let mut cache = Cache::default();
+        cache.update("prod", 0, Ok(vec!["b".to_string(), "a".to_string()]));
+        cache.update("dev", 0, Ok(vec!["a".to_string()]));
+        cache.update("old", 0, Ok(vec!["a".to_string()]));
+        let clusters = vec!["prod".to_string(), "dev".to_string()];
+        assert_eq!(
+            cache.entries(&clusters, "::"),
+            vec!["a::dev", "a::prod", "b::prod"]
+        );
+    }
+
+    #[test]
+    fn test_is_outdated() {
+        let c = ClusterCache {
+            last_attempt: now() - 100,
+            ..Default::default()
+        };
+        assert!(!c.is_outdated(3600, 0));
+        assert!(c.is_outdated(50, 0));
+        assert!(c.is_outdated(3600, 42));
+    }
+
+    #[test]
+    fn test_read_legacy_file() {
+        let path = std::env::temp_dir().join("ktk-test-legacy-cache");
+        std::fs::write(&path, "ns::prod\nns2::prod\n").unwrap();
+        assert_eq!(Cache::read(path.to_str().unwrap()), Cache::default());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_lock() {
+        let path = std::env::temp_dir().join("ktk-test-lock-cache");
+        let cache = path.to_str().unwrap();
+        let lock = CacheLock::acquire(cache);
+        assert!(lock.is_some());
+        assert!(CacheLock::is_locked(cache));
+        assert!(CacheLock::acquire(cache).is_none());
+        drop(lock);
+        assert!(!CacheLock::is_locked(cache));
+        assert!(CacheLock::wait(cache).is_some());
+        assert!(CacheLock::acquire(cache).is_some());
+    }
+}
@@ -1,18 +1,22 @@
 //! Read ktk yaml file and load Context
+use crate::cache::Cache;
+use crate::discovery;
 use crate::kube::{self, Cluster};
+use crate::kubeconfig;
 use crate::ohmyposh::Config as ThemeConfig;
 use crate::terminal::kitty::Tabcolor;
 use clap::crate_name;
+use serde::Deserialize;
 use serde_yaml::Value;
 
+use std::collections::HashMap;
+use std::fmt;
 use std::fs;
-use std::fs::File;
-use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process;
-use std::time::SystemTime;
+use std::time::Duration;
 
-use log::{error, info};
+use log::{error, info, warn};
 use owo_colors::OwoColorize;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -25,10 +29,584 @@ pub struct Context {
     pub tabprefix: String,
     pub clusters: Vec<Cluster>,
     pub ohmyposhfile: String,
+    pub scan_timeout: Option<Duration>,
+    pub max_parallel: usize,
+    pub preview: bool,
+    pub preview_maxage: u64,
+    pub preview_window: String,
+    pub history_filename: String,
+    pub snapshot_filename: String,
+    pub favorites: Vec<Favorite>,
+    pub sessions: Vec<Session>,
+    pub elevated_tabcolor: Tabcolor, // tabs opened with an elevated user or without impersonation
 }
 
-pub fn new_gradient(g: &str) -> colorous::Gradient {
-    match g {
+/// Typed content of the ktk yaml file.
+///
+/// Top level keys starting with a dot (`.workdir`, `.kubeconfig`, ...) only
+/// hold yaml anchors and are dropped before deserialization.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    #[serde(default)]
+    pub global: GlobalConfig,
+    #[serde(default)]
+    pub clusters: Vec<ClusterConfig>,
+    #[serde(default)]
+    pub favorites: Vec<Favorite>,
+    #[serde(default)]
+    pub sessions: Vec<Session>,
+    #[serde(default)]
+    pub discovery: DiscoveryConfig,
+}
+
+/// Kubeconfig files whose contexts are added to the clusters, the clusters
+/// of the `clusters` list with the same name or context take precedence.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DiscoveryConfig {
+    #[serde(default)]
+    pub paths: Vec<String>, // files, folders or globs
+    #[serde(rename = "kubeconfig-env", default)]
+    pub kubeconfig_env: bool, // files of the KUBECONFIG variable
+    #[serde(default)]
+    pub workdir: WorkdirConfig, // `{cluster}` is replaced by the cluster name
+    pub maxage: Option<u64>,
+}
+
+impl DiscoveryConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.paths.is_empty() || self.kubeconfig_env
+    }
+}
+
+/// Namespace shown first in the search, it can be opened with its alias.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Favorite {
+    pub namespace: String,
+    pub cluster: String,
+    pub alias: Option<String>,
+}
+
+/// Named group of namespaces opened together by `ktk session open`.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Session {
+    pub name: String,
+    #[serde(default)]
+    pub tabs: Vec<SessionTab>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SessionTab {
+    pub namespace: String,
+    pub cluster: String,
+    pub user: Option<String>, // user of the kubeconfig, the default one otherwise
+    pub command: Option<String>, // typed in the shell of the new tab
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GlobalConfig {
+    pub kubetmp: Option<String>,
+    pub separator: Option<String>,
+    #[serde(default)]
+    pub completion: CompletionConfig,
+    pub tabprefix: Option<String>,
+    #[serde(default)]
+    pub gradient: GradientConfig,
+    #[serde(rename = "oh-my-posh", default)]
+    pub ohmyposh: OhMyPoshConfig,
+    #[serde(default)]
+    pub scan: ScanConfig,
+    #[serde(default)]
+    pub preview: PreviewConfig,
+    #[serde(default)]
+    pub history: HistoryConfig,
+    #[serde(default)]
+    pub snapshot: SnapshotConfig,
+    #[serde(rename = "elevated-color")]
+    pub elevated_color: Option<String>,
+    #[serde(rename = "protected-color")]
+    pub protected_color: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HistoryConfig {
+    pub file: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SnapshotConfig {
+    pub file: Option<String>,
+}
+
+/// Preview pane of the fuzzy finder with the details of the namespace.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PreviewConfig {
+    pub enabled: Option<bool>,
+    pub maxage: Option<u64>,
+    pub window: Option<String>,
+}
+
+/// Deadlines of the namespaces scan, the cluster values are the defaults of
+/// the `kubeconfig` section of each cluster.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScanConfig {
+    pub timeout: Option<u64>,
+    #[serde(rename = "max-parallel")]
+    pub max_parallel: Option<usize>,
+    #[serde(rename = "cluster-timeout")]
+    pub cluster_timeout: Option<u64>,
+    #[serde(rename = "get-timeout-sec")]
+    pub get_timeout_sec: Option<u64>,
+    #[serde(rename = "connect-timeout-msec")]
+    pub connect_timeout_msec: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CompletionConfig {
+    pub file: Option<String>,
+    pub maxage: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GradientConfig {
+    pub name: Option<String>,
+    pub reverse: Option<bool>,
+    pub darken: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OhMyPoshConfig {
+    pub file: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClusterConfig {
+    pub name: String,
+    #[serde(default)]
+    pub disabled: bool,
+    #[serde(default)]
+    pub protected: bool, // confirmation before opening a tab, with its own color
+    pub maxage: Option<u64>,
+    #[serde(default)]
+    pub workdir: WorkdirConfig,
+    #[serde(default)]
+    pub kubeconfig: KubeconfigConfig,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorkdirConfig {
+    pub path: Option<String>,
+    pub subdir: Option<String>,
+    pub prefixns: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KubeconfigConfig {
+    pub path: Option<String>,
+    pub file: Option<String>,
+    pub timeout: Option<u64>,
+    #[serde(rename = "get-timeout-sec")]
+    pub get_timeout_sec: Option<u64>,
+    #[serde(rename = "connect-timeout-msec")]
+    pub connect_timeout_msec: Option<u64>,
+    pub context: Option<String>, // context of the file used, the current one by default
+    #[serde(rename = "default-user")]
+    pub default_user: Option<String>, // user of the tabs, the one of the context by default
+    pub impersonate: Option<String>, // user impersonated by the default user of the tabs
+    #[serde(rename = "elevated-users", default)]
+    pub elevated_users: Vec<String>, // users whose tabs have the elevated color
+    #[serde(rename = "all-contexts")]
+    pub all_contexts: Option<bool>, // one cluster for each context of the file
+}
+
+/// Error raised when the ktk yaml file can't be loaded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    pub file: PathBuf,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub path: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{line}")?;
+            if let Some(column) = self.column {
+                write!(f, ":{column}")?;
+            }
+        }
+        if let Some(path) = &self.path {
+            write!(f, ": {path}")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl ConfigError {
+    fn new(file: &Path, message: String) -> ConfigError {
+        ConfigError {
+            file: file.to_path_buf(),
+            line: None,
+            column: None,
+            path: None,
+            message,
+        }
+    }
+}
+
+// Content line of the yaml source, without its indentation
+#[derive(Debug, Clone, Copy)]
+struct SourceLine<'a> {
+    line: usize,
+    column: usize,
+    text: &'a str,
+}
+
+fn is_item(text: &str) -> bool {
+    text == "-" || text.starts_with("- ")
+}
+
+// The text is the key `key` of a mapping, quoted or not
+fn is_key(text: &str, key: &str) -> Option<usize> {
+    [key.to_string(), format!("\"{key}\""), format!("'{key}'")]
+        .iter()
+        .find(|k| text.starts_with(&format!("{k}:")))
+        .map(|k| k.len() + 1)
+}
+
+/// Line and column of the value at `path` (like `clusters[3].disabled`) in
+/// the yaml source, following the nesting of the block mappings and
+/// sequences. When the path can't be followed to the end (flow style,
+/// value merged from an anchor), the location of its deepest found parent
+/// is returned.
+fn locate_path(source: &str, path: &str) -> Option<(usize, usize)> {
+    let mut lines: Vec<SourceLine> = source
+        .lines()
+        .enumerate()
+        .filter_map(|(i, l)| {
+            let text = l.trim_start();
+            (!text.is_empty() && !text.starts_with('#')).then(|| SourceLine {
+                line: i + 1,
+                column: l.len() - text.len(),
+                text,
+            })
+        })
+        .collect();
+    let mut found = None;
+    let segments = path.split('.').filter(|s| !s.is_empty()).flat_map(|s| {
+        let mut parts = s.split('[');
+        let key = parts
+            .next()
+            .filter(|k| !k.is_empty())
+            .map(|k| (Some(k), None));
+        let indexes = parts.filter_map(|i| i.trim_end_matches(']').parse::<usize>().ok());
+        key.into_iter().chain(indexes.map(|i| (None, Some(i))))
+    });
+    for segment in segments {
+        let Some(first) = lines.first() else {
+            break;
+        };
+        let indent = first.column;
+        // Lines of the children of the node starting at `pos`
+        let end_of = |pos: usize, lines: &[SourceLine]| {
+            lines[pos + 1..]
+                .iter()
+                .position(|l| l.column < indent || (l.column == indent && !is_item(l.text)))
+                .map_or(lines.len(), |e| pos + 1 + e)
+        };
+        match segment {
+            (Some(key), _) => {
+                let Some((pos, len)) = lines
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, l)| l.column == indent)
+                    .find_map(|(i, l)| is_key(l.text, key).map(|len| (i, len)))
+                else {
+                    break;
+                };
+                let l = lines[pos];
+                found = Some((l.line, l.column + 1));
+                let value = l.text[len..].trim_start();
+                lines = match value.is_empty() {
+                    true => lines[pos + 1..end_of(pos, &lines)].to_vec(),
+                    false => vec![SourceLine {
+                        column: l.column + l.text.len() - value.len(),
+                        text: value,
+                        ..l
+                    }],
+                };
+            }
+            (None, Some(index)) => {
+                let items: Vec<usize> = (0..lines.len())
+                    .filter(|&i| lines[i].column == indent && is_item(lines[i].text))
+                    .collect();
+                let Some(&pos) = items.get(index) else {
+                    break;
+                };
+                let end = items.get(index + 1).copied().unwrap_or(end_of(pos, &lines));
+                let l = lines[pos];
+                found = Some((l.line, l.column + 1));
+                // The content of the item starts after its dash
+                let value = l.text[1..].trim_start();
+                let mut item = lines[pos + 1..end].to_vec();
+                if !value.is_empty() {
+                    item.insert(
+                        0,
+                        SourceLine {
+                            column: l.column + l.text.len() - value.len(),
+                            text: value,
+                            ..l
+                        },
+                    );
+                }
+                lines = item;
+            }
+            _ => break,
+        }
+    }
+    found
+}
+
+impl ConfigFile {
+    /// Load and check the structure of the ktk yaml file.
+    pub fn load(file: &Path) -> Result<ConfigFile, ConfigError> {
+        let source = fs::read_to_string(file)
+            .map_err(|e| ConfigError::new(file, format!("unable to read file: {e}")))?;
+        ConfigFile::parse(&source).map_err(|e| ConfigError {
+            file: file.to_path_buf(),
+            ..e
+        })
+    }
+
+    pub fn parse(source: &str) -> Result<ConfigFile, ConfigError> {
+        let syntax_error = |e: serde_yaml::Error| {
+            let location = e.location();
+            ConfigError {
+                line: location.as_ref().map(|l| l.line()),
+                column: location.as_ref().map(|l| l.column()),
+                ..ConfigError::new(Path::new(""), e.to_string())
+            }
+        };
+        let mut cfg: Value = serde_yaml::from_str(source).map_err(syntax_error)?;
+        if cfg.is_null() {
+            return Ok(ConfigFile::default());
+        }
+        // Merge anchors in yaml file
+        cfg.apply_merge().map_err(syntax_error)?;
+        if let Value::Mapping(map) = &mut cfg {
+            map.retain(|k, _| !k.as_str().is_some_and(|s| s.starts_with('.')));
+        }
+        serde_path_to_error::deserialize(cfg).map_err(|e| {
+            let path = e.path().to_string();
+            let message = e.into_inner().to_string();
+            let location = locate_path(source, &path);
+            ConfigError {
+                line: location.map(|l| l.0),
+                column: location.map(|l| l.1),
+                path: if path == "." { None } else { Some(path) },
+                ..ConfigError::new(Path::new(""), message)
+            }
+        })
+    }
+
+    /// Returns the problems that don't prevent loading the file but are
+    /// most likely mistakes.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let separator = self.global.separator.as_deref().unwrap_or("::");
+        if let Some(name) = &self.global.gradient.name
+            && gradient_by_name(name).is_none()
+        {
+            warnings.push(format!(
+                "global.gradient.name: unknown gradient `{name}`, turbo is used"
+            ));
+        }
+        for (i, cl) in self.clusters.iter().enumerate() {
+            if cl.name.is_empty() {
+                warnings.push(format!("clusters[{i}].name: empty cluster name"));
+            } else if self.clusters[..i].iter().any(|c| c.name == cl.name) {
+                warnings.push(format!(
+                    "clusters[{i}].name: duplicate cluster name `{}`, only the first one is used",
+                    cl.name
+                ));
+            }
+            // The name could not be split from the namespace in the search
+            if cl.kubeconfig.all_contexts.unwrap_or(false) && !cl.disabled {
+                for (name, ctx) in all_contexts(cl).unwrap_or_default() {
+                    if name.contains(separator) {
+                        warnings.push(format!(
+                            "clusters[{i}].kubeconfig.all-contexts: context `{ctx}` is ignored, the name `{name}` contains the separator `{separator}`"
+                        ));
+                    }
+                }
+            }
+        }
+        for (i, path) in self.discovery.paths.iter().enumerate() {
+            if let Err(e) = glob::Pattern::new(&expand_path(path)) {
+                warnings.push(format!(
+                    "discovery.paths[{i}]: invalid pattern `{path}`: {e}"
+                ));
+            }
+        }
+        // The clusters of the favorites and sessions are checked by
+        // `Context::warnings`, with the expanded and discovered clusters
+        for (i, fav) in self.favorites.iter().enumerate() {
+            if let Some(alias) = &fav.alias
+                && self.favorites[..i]
+                    .iter()
+                    .any(|f| f.alias.as_ref() == Some(alias))
+            {
+                warnings.push(format!(
+                    "favorites[{i}].alias: duplicate alias `{alias}`, only the first one is used"
+                ));
+            }
+        }
+        for (i, session) in self.sessions.iter().enumerate() {
+            if self.sessions[..i].iter().any(|s| s.name == session.name) {
+                warnings.push(format!(
+                    "sessions[{i}].name: duplicate session name `{}`, only the first one is used",
+                    session.name
+                ));
+            }
+            if session.tabs.is_empty() {
+                warnings.push(format!("sessions[{i}].tabs: empty session"));
+            }
+        }
+        warnings
+    }
+}
+
+/// Expand a leading `~` and the environment variables (`$VAR`, `${VAR}` and
+/// `${VAR:-default}`) of a path read in the config file.
+pub fn expand_path(path: &str) -> String {
+    expand_path_with(path, |name| std::env::var(name).ok())
+}
+
+// Expansion with the variables given by `lookup`, the tests don't change
+// the environment shared by their threads
+fn expand_path_with<F>(path: &str, lookup: F) -> String
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut result = String::new();
+    let mut rest = path;
+    if rest == "~" || rest.starts_with("~/") {
+        let home = dirs::home_dir().unwrap_or_default();
+        result.push_str(home.display().to_string().trim_end_matches('/'));
+        rest = &rest[1..];
+    }
+    while let Some(pos) = rest.find('$') {
+        result.push_str(&rest[..pos]);
+        rest = &rest[pos + 1..];
+        let (name, default, len) = if let Some(braced) = rest.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => {
+                    let inner = &braced[..end];
+                    match inner.split_once(":-") {
+                        Some((name, default)) => (name, Some(default), end + 2),
+                        None => (inner, None, end + 2),
+                    }
+                }
+                None => ("", None, 0),
+            }
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (&rest[..end], None, end)
+        };
+        if name.is_empty() {
+            result.push('$');
+            continue;
+        }
+        match lookup(name) {
+            Some(v) if !v.is_empty() => result.push_str(&v),
+            _ => match default {
+                Some(d) => result.push_str(d),
+                None => warn!("environment variable {name} used in {path} is not set"),
+            },
+        }
+        rest = &rest[len..];
+    }
+    result.push_str(rest);
+    result
+}
+
+pub fn kubeconfig_path(kubeconfig: &KubeconfigConfig) -> String {
+    format!(
+        "{}/{}",
+        expand_path(kubeconfig.path.as_deref().unwrap_or_default()),
+        expand_path(kubeconfig.file.as_deref().unwrap_or_default())
+    )
+}
+
+// Names and contexts of the clusters of the contexts of the kubeconfig file
+// of an all-contexts cluster
+fn all_contexts(c: &ClusterConfig) -> Result<Vec<(String, String)>, String> {
+    let path = kubeconfig_path(&c.kubeconfig);
+    if !Path::new(&path).is_file() {
+        return Err(format!(
+            "cluster {}: kubeconfig file {path} not found",
+            c.name
+        ));
+    }
+    match kubeconfig::Kubeconfig::new(path.clone()) {
+        Ok(k) => Ok(k
+            .context_names()
+            .into_iter()
+            .map(|ctx| (format!("{}-{ctx}", c.name), ctx))
+            .collect()),
+        Err(e) => Err(format!(
+            "cluster {}: unable to read the contexts of {path}: {e}",
+            c.name
+        )),
+    }
+}
+
+/// Names and contexts of the clusters given by a cluster of the config file,
+/// one for each context of its kubeconfig file with `all-contexts`. The
+/// names with the separator, reported by `ConfigFile::warnings`, are skipped.
+fn cluster_contexts(c: &ClusterConfig, separator: &str) -> Vec<(String, Option<String>)> {
+    let single = vec![(c.name.clone(), c.kubeconfig.context.clone())];
+    if !c.kubeconfig.all_contexts.unwrap_or(false) || c.disabled {
+        return single;
+    }
+    match all_contexts(c) {
+        Ok(v) if !v.is_empty() => v
+            .into_iter()
+            .filter(|(name, _)| !name.contains(separator))
+            .map(|(name, ctx)| (name, Some(ctx)))
+            .collect(),
+        Ok(_) => single,
+        Err(e) => {
+            warn!("{e}");
+            single
+        }
+    }
+}
+
+fn gradient_by_name(g: &str) -> Option<colorous::Gradient> {
+    let gradient = match g.to_lowercase().replace("_", "").as_str() {
         "blues" => colorous::BLUES,
         "bluegreen" => colorous::BLUE_GREEN,
         "bluepurple" => colorous::BLUE_PURPLE,
@@ -67,32 +645,67 @@ pub fn new_gradient(g: &str) -> colorous::Gradient {
         "yellowgreenblue" => colorous::YELLOW_GREEN_BLUE,
         "yelloworangebrown" => colorous::YELLOW_ORANGE_BROWN,
         "yelloworangered" => colorous::YELLOW_ORANGE_RED,
-        _ => colorous::TURBO,
+        _ => return None,
+    };
+    Some(gradient)
+}
+
+// User of the context of a kubeconfig file, of its current context by default
+fn context_user(path: &str, context: Option<&str>) -> Option<String> {
+    if !Path::new(path).is_file() {
+        return None;
     }
+    let mut kcf = kubeconfig::Kubeconfig::new(path.to_string()).ok()?;
+    if let Some(context) = context {
+        kcf.select_context(context);
+    }
+    Some(kcf.user_context())
+}
+
+// Colors of the tabs with the background `color` when they are active
+fn fixed_tabcolor(key: &str, color: &str, darken: bool) -> Tabcolor {
+    let mut tabcolor = Tabcolor::new();
+    match csscolorparser::parse(color) {
+        Ok(c) => {
+            let [r, g, b, _] = c.to_rgba8();
+            tabcolor.set_color(colorous::Color { r, g, b }, darken);
+        }
+        Err(e) => warn!("{key}: invalid color `{color}`: {e}"),
+    }
+    tabcolor
+}
+
+pub fn new_gradient(g: &str) -> colorous::Gradient {
+    gradient_by_name(g).unwrap_or(colorous::TURBO)
 }
 
 impl Context {
-    pub fn new(file: &PathBuf, notimeout: bool) -> Context {
+    pub fn new(file: &Path, notimeout: bool) -> Context {
         //! Load config file in struct Context
-        let f = std::fs::File::open(file).expect("Could not open file.");
-        // Deserialize yaml file
-        let mut cfg: Value = match serde_yaml::from_reader(f) {
+        let cfg = match ConfigFile::load(file) {
             Ok(v) => v,
             Err(e) => {
-                error!("Unabled to load config file {} : {e}", file.display());
+                error!("Unabled to load config file {e}");
                 process::exit(52)
             }
         };
-        // Merge anchrors in yaml file
-        cfg.apply_merge().unwrap();
+        for w in cfg.warnings() {
+            warn!("{}: {w}", file.display());
+        }
+        let conf = Context::from_config(cfg, file, notimeout);
+        for w in conf.warnings() {
+            warn!("{}: {w}", file.display());
+        }
+        conf
+    }
 
+    pub fn from_config(cfg: ConfigFile, file: &Path, notimeout: bool) -> Context {
         // Populate Context struct
-        let value_string = |v: &serde_yaml::Value, def: &str| {
-            v.as_str().map_or(def.to_string(), |s| s.to_string())
-        };
-        let kubetmp = value_string(
-            &cfg["global"]["kubetmp"],
-            format!("/tmp/{}", crate_name!()).as_str(),
+        let global = cfg.global;
+        let kubetmp = expand_path(
+            &global
+                .kubetmp
+                .unwrap_or_else(|| format!("/tmp/{}", crate_name!())),
         );
 
         let pathktmp = Path::new(&kubetmp);
@@ -102,9 +715,13 @@ impl Context {
             process::exit(53)
         }
 
-        let separator = value_string(&cfg["global"]["separator"], "::");
-        let completion_filename =
-            value_string(&cfg["global"]["completion"]["file"], "/tmp/tkcomplete");
+        let separator = global.separator.unwrap_or("::".to_string());
+        let completion_filename = expand_path(
+            &global
+                .completion
+                .file
+                .unwrap_or("/tmp/tkcomplete".to_string()),
+        );
 
         let pathcf = Path::new(&completion_filename);
         let parentcf = pathcf.parent().unwrap();
@@ -115,79 +732,208 @@ impl Context {
             process::exit(53)
         }
 
-        let maxage = cfg["global"]["completion"]["maxage"]
-            .as_u64()
-            .unwrap_or(3600);
-        let tabprefix = value_string(&cfg["global"]["tabprefix"], "");
-        let gradient = new_gradient(
-            value_string(&cfg["global"]["gradient"]["name"], "")
-                .to_lowercase()
-                .replace("_", "")
-                .as_str(),
-        );
-        let reverse = cfg["global"]["gradient"]["reverse"]
-            .as_bool()
-            .unwrap_or(true);
-        let darken = cfg["global"]["gradient"]["darken"]
-            .as_bool()
-            .unwrap_or(false);
+        let maxage = global.completion.maxage.unwrap_or(3600);
+        let tabprefix = global.tabprefix.unwrap_or_default();
+        let gradient = new_gradient(global.gradient.name.unwrap_or_default().as_str());
+        let reverse = global.gradient.reverse.unwrap_or(true);
+        let darken = global.gradient.darken.unwrap_or(false);
 
-        let ohmyposhfile = value_string(&cfg["global"]["oh-my-posh"]["file"], "");
+        let ohmyposhfile = expand_path(&global.ohmyposh.file.unwrap_or_default());
 
-        let mut i = 0;
-        let mut clusters: Vec<Cluster> = Vec::new();
-        let value_or_empty =
-            |v: &serde_yaml::Value| v.as_str().map_or("".to_string(), |s| s.to_string());
+        // With the wait option, clusters have one minute to answer
+        // and the whole scan is not limited.
+        let scan = global.scan;
+        let scan_timeout = match notimeout {
+            true => None,
+            false => Some(Duration::from_secs(scan.timeout.unwrap_or(30))),
+        };
+        let max_parallel = scan.max_parallel.unwrap_or(8);
 
-        let mut count_cluster = 0;
-        while cfg["clusters"][count_cluster].is_mapping() {
-            count_cluster += 1;
-        }
+        let preview = global.preview.enabled.unwrap_or(false);
+        let preview_maxage = global.preview.maxage.unwrap_or(30);
+        let preview_window = global.preview.window.unwrap_or("right:50%".to_string());
 
-        while cfg["clusters"][i].is_mapping() {
-            let name = value_or_empty(&cfg["clusters"][i]["name"]);
-            let kubeconfig_path = format!(
-                "{}/{}",
-                value_string(&cfg["clusters"][i]["kubeconfig"]["path"], ""),
-                value_string(&cfg["clusters"][i]["kubeconfig"]["file"], "")
-            );
+        let history_filename = match global.history.file {
+            Some(f) => expand_path(&f),
+            None => {
+                let datadir = dirs::data_dir().unwrap_or_else(|| PathBuf::from("/tmp"));
+                format!("{}/{}/history.json", datadir.display(), crate_name!())
+            }
+        };
+        // Not in kubetmp, which may be wiped at reboot
+        let snapshot_filename = match global.snapshot.file {
+            Some(f) => expand_path(&f),
+            None => {
+                let datadir = dirs::data_dir().unwrap_or_else(|| PathBuf::from("/tmp"));
+                format!("{}/{}/snapshot.json", datadir.display(), crate_name!())
+            }
+        };
+
+        // A cluster with all-contexts is expanded in one cluster for each
+        // context of its kubeconfig file, named `{cluster}-{context}`
+        let mut expanded: Vec<(ClusterConfig, Vec<_>)> = cfg
+            .clusters
+            .into_iter()
+            .map(|c| {
+                let variants = cluster_contexts(&c, &separator);
+                (c, variants)
+            })
+            .collect();
+        let declared: Vec<(String, String, Option<String>)> = expanded
+            .iter()
+            .flat_map(|(c, variants)| {
+                let path = kubeconfig_path(&c.kubeconfig);
+                variants
+                    .iter()
+                    .map(move |(name, context)| (name.clone(), path.clone(), context.clone()))
+            })
+            .collect();
+        expanded.extend(
+            discovery::clusters(&cfg.discovery, &declared, &kubetmp, &separator)
+                .into_iter()
+                .map(|c| {
+                    let variants = vec![(c.name.clone(), c.kubeconfig.context.clone())];
+                    (c, variants)
+                }),
+        );
+        let count_cluster = expanded.iter().map(|(_, v)| v.len()).sum::<usize>();
+        let elevated_tabcolor = fixed_tabcolor(
+            "global.elevated-color",
+            global.elevated_color.as_deref().unwrap_or("#d70000"),
+            darken,
+        );
+        let protected_tabcolor = fixed_tabcolor(
+            "global.protected-color",
+            global.protected_color.as_deref().unwrap_or("#8700af"),
+            darken,
+        );
+        let mut clusters: Vec<Cluster> = Vec::new();
+        let mut i = 0;
+        for (c, variants) in expanded {
+            let kubeconfig_path = kubeconfig_path(&c.kubeconfig);
             let workdir = format!(
                 "{}/{}",
-                value_string(&cfg["clusters"][i]["workdir"]["path"], ""),
-                value_string(&cfg["clusters"][i]["workdir"]["subdir"], "")
+                expand_path(&c.workdir.path.unwrap_or_default()),
+                expand_path(&c.workdir.subdir.unwrap_or_default())
             );
-            let prefixns = value_string(&cfg["clusters"][i]["workdir"]["prefixns"], "");
-            let mut tabcolor = Tabcolor::new();
-            if reverse {
-                tabcolor.set_tab_color(gradient, darken, count_cluster - i, count_cluster + 1);
+            let prefixns = c.workdir.prefixns.unwrap_or_default();
+            let (timeout, get_timeout, connect_timeout) = if notimeout {
+                (60, 60, 60_000)
             } else {
-                tabcolor.set_tab_color(gradient, darken, i, count_cluster + 1);
-            }
-            let disabled = cfg["clusters"][i]["disabled"].as_bool().unwrap_or(false);
-            let timeout = cfg["clusters"][i]["kubeconfig"]["timeout"]
-                .as_u64()
-                .map_or(10, |t| if notimeout { 60 } else { t });
-            let cl: Cluster = Cluster {
-                name,
-                kubeconfig_path,
-                workdir,
-                prefixns,
-                disabled,
-                timeout: timeout.try_into().unwrap_or(10),
-                tabcolor,
+                let t = c.kubeconfig.timeout.or(scan.cluster_timeout).unwrap_or(10);
+                (
+                    t,
+                    c.kubeconfig
+                        .get_timeout_sec
+                        .or(scan.get_timeout_sec)
+                        .unwrap_or(t),
+                    c.kubeconfig
+                        .connect_timeout_msec
+                        .or(scan.connect_timeout_msec)
+                        .unwrap_or(t * 1000),
+                )
             };
-            clusters.push(cl);
-            i += 1;
+            for (name, context) in variants {
+                let mut tabcolor = Tabcolor::new();
+                if c.protected {
+                    tabcolor = protected_tabcolor.clone();
+                } else if reverse {
+                    tabcolor.set_tab_color(gradient, darken, count_cluster - i, count_cluster + 1);
+                } else {
+                    tabcolor.set_tab_color(gradient, darken, i, count_cluster + 1);
+                }
+                i += 1;
+                // The user of the context is only needed to tell if an
+                // elevated user is the default one
+                let default_user = match c.kubeconfig.elevated_users.is_empty() {
+                    true => c.kubeconfig.default_user.clone(),
+                    false => c
+                        .kubeconfig
+                        .default_user
+                        .clone()
+                        .or_else(|| context_user(&kubeconfig_path, context.as_deref())),
+                };
+                let cl: Cluster = Cluster {
+                    name,
+                    kubeconfig_path: kubeconfig_path.clone(),
+                    context,
+                    default_user,
+                    impersonate: c.kubeconfig.impersonate.clone(),
+                    elevated_users: c.kubeconfig.elevated_users.clone(),
+                    protected: c.protected,
+                    workdir: workdir.clone(),
+                    prefixns: prefixns.clone(),
+                    disabled: c.disabled,
+                    maxage: c.maxage.unwrap_or(maxage),
+                    timeout: timeout.try_into().unwrap_or(10),
+                    get_timeout: get_timeout.try_into().unwrap_or(10),
+                    connect_timeout,
+                    tabcolor,
+                };
+                clusters.push(cl);
+            }
         }
         Context {
             kubetmp,
             separator,
             completion_filename,
-            config_filename: (file).to_path_buf(),
+            config_filename: file.to_path_buf(),
             maxage,
             tabprefix,
             clusters,
             ohmyposhfile,
+            scan_timeout,
+            max_parallel,
+            preview,
+            preview_maxage,
+            preview_window,
+            history_filename,
+            snapshot_filename,
+            favorites: cfg.favorites,
+            sessions: cfg.sessions,
+            elevated_tabcolor,
+        }
+    }
+
+    /// Favorites and session tabs on an unknown cluster, the clusters
+    /// expanded from all the contexts and the discovered ones are known.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let unknown = |name: &str| self.cluster_named(name).is_none();
+        for (i, fav) in self.favorites.iter().enumerate() {
+            if unknown(&fav.cluster) {
+                warnings.push(format!(
+                    "favorites[{i}].cluster: unknown cluster `{}`",
+                    fav.cluster
+                ));
+            }
+        }
+        for (i, session) in self.sessions.iter().enumerate() {
+            for (j, tab) in session.tabs.iter().enumerate() {
+                if unknown(&tab.cluster) {
+                    warnings.push(format!(
+                        "sessions[{i}].tabs[{j}].cluster: unknown cluster `{}`",
+                        tab.cluster
+                    ));
+                }
+            }
+        }
+        warnings
+    }
+
+    /// Colors of the tabs of the cluster opened with `user`, the ones of the
+    /// cluster unless the user is one of its elevated users, or the default
+    /// user no longer impersonates another one.
+    pub fn tabcolor(&self, cluster: &Cluster, user: Option<&str>) -> Tabcolor {
+        let Some(user) = user else {
+            return cluster.tabcolor.clone();
+        };
+        let elevated = cluster.default_user.as_deref() != Some(user)
+            && cluster.elevated_users.iter().any(|u| u == user);
+        // Any user given explicitly drops the impersonation
+        match elevated || cluster.impersonate.is_some() {
+            true => self.elevated_tabcolor.clone(),
+            false => cluster.tabcolor.clone(),
         }
     }
 
@@ -312,102 +1058,409 @@ impl Context {
         println!("Update Oh-My-Posh config file : {}", self.ohmyposhfile);
     }
 
-    #[allow(dead_code)]
-    pub fn completion_file_older_than_config(&self) -> bool {
-        if !Path::new(&self.completion_filename).exists() {
-            return true;
-        }
-
-        let complete_file = self.completion_filename.clone();
-        let config_file = self.config_filename.clone();
-
-        let complete_time = match fs::metadata(complete_file).unwrap().modified() {
-            Ok(time) => time,
-            Err(error) => {
-                error!("Problem opening the file: {error:?}");
-                process::exit(10)
-            }
-        };
-
-        let config_time = match fs::metadata(config_file).unwrap().modified() {
-            Ok(time) => time,
-            Err(error) => {
-                error!("Problem opening the file: {error:?}");
-                process::exit(10)
-            }
-        };
-
-        complete_time < config_time
-    }
-
-    pub fn completion_file_older_than_maxage(&self) -> bool {
-        if !Path::new(&self.completion_filename).exists() {
-            return true;
-        }
-
-        let now = SystemTime::now();
-
-        let file = self.completion_filename.clone();
-        let maxage = self.maxage;
-
-        if let Ok(time) = fs::metadata(file).unwrap().modified() {
-            let diff = now.duration_since(time).unwrap().as_secs();
-            return diff > maxage;
-        } else {
-            error!("Not supported on this platform");
-        }
-        true
+    pub fn outdated_clusters(&self) -> Vec<Cluster> {
+        // returns the active clusters whose cache is older than their maxage
+        // or whose settings have changed
+        let cache = Cache::read(&self.completion_filename);
+        self.clusters
+            .iter()
+            .filter(|cl| !cl.disabled)
+            .filter(|cl| match cache.clusters.get(&cl.name) {
+                Some(c) => c.is_outdated(cl.maxage, cl.scan_hash()),
+                None => true,
+            })
+            .cloned()
+            .collect()
     }
 
-    pub fn update_completion_file(&self) {
-        // fetch all namespace in all clusters
-        let data_compl = kube::get_all_ns(self.clusters.clone(), self.separator.clone());
-
-        // Do not change the completion file if no cluster can be reached.
-        if data_compl.is_empty() {
-            error!("no cluster is reachable");
-            return;
-        }
+    pub fn update_completion_file<F>(&self, clusters: Vec<Cluster>, mut on_result: F)
+    where
+        F: FnMut(&str, &Result<Vec<String>, String>),
+    {
         let file = self.completion_filename.clone();
         // Create directory if it don't exist
         let path = Path::new(&file);
         let parent = path.parent().unwrap();
         fs::create_dir_all(parent).expect("Could not create destination dir");
 
-        info!("update {file}");
-        let str: String = data_compl
+        // fetch all namespace in the clusters, the cache is saved after each
+        // answer so that the scan can be interrupted
+        let hashes: HashMap<String, u64> = clusters
             .iter()
-            .cloned()
-            .map(|x| if !x.is_empty() { format!("{x}\n") } else { x })
+            .map(|c| (c.name.clone(), c.scan_hash()))
             .collect();
+        let scanner = kube::Scanner::new(self.max_parallel);
+        scanner.get_all_ns(clusters, self.scan_timeout, |name, result| {
+            on_result(name, &result);
+            info!("update {file} for {name}");
+            // The cache may have been updated by another ktk since the start of the scan
+            let mut cache = Cache::read(&file);
+            cache.update(name, hashes[name], result);
+            cache.write(&file);
+        });
+    }
 
-        let mut f = File::create(file.clone()).expect("Couldn't open file");
+    /// Details of a namespace shown in the preview of the fuzzy search,
+    /// they are kept `preview_maxage` seconds under kubetmp.
+    pub fn namespace_details(&self, namespace: &str, clustername: &str) -> Result<String, String> {
+        let cl = self
+            .cluster_named(clustername)
+            .ok_or(format!("cluster {clustername} not found in config file"))?;
+        let file = format!(
+            "{}/preview/{}/{}",
+            self.kubetmp,
+            clustername.replace('/', "_"),
+            namespace.replace('/', "_")
+        );
+        if let Ok(time) = fs::metadata(&file).and_then(|m| m.modified())
+            && time.elapsed().unwrap_or_default() < Duration::from_secs(self.preview_maxage)
+            && let Ok(details) = fs::read_to_string(&file)
+        {
+            return Ok(details);
+        }
+        let details = kube::Scanner::new(1).namespace_details(cl, namespace)?;
+        let path = Path::new(&file);
+        if fs::create_dir_all(path.parent().unwrap()).is_ok()
+            && let Err(e) = fs::write(path, &details)
+        {
+            info!("Couldn't write preview {file}: {e}");
+        }
+        Ok(details)
+    }
 
-        f.write_all(str.as_bytes()).expect("Couldn't write in file");
+    // returns the `namespace<sep>cluster` favorites of the active clusters
+    pub fn favorite_entries(&self) -> Vec<String> {
+        let clusters = self.clusters_names();
+        self.favorites
+            .iter()
+            .filter(|f| clusters.contains(&f.cluster))
+            .map(|f| format!("{}{}{}", f.namespace, self.separator, f.cluster))
+            .collect()
+    }
+
+    // returns the `namespace<sep>cluster` favorite with this alias
+    pub fn favorite_by_alias(&self, alias: &str) -> Option<String> {
+        self.favorites
+            .iter()
+            .find(|f| f.alias.as_deref() == Some(alias))
+            .map(|f| format!("{}{}{}", f.namespace, self.separator, f.cluster))
+    }
+
+    pub fn session_named(&self, name: &str) -> Option<&Session> {
+        self.sessions.iter().find(|s| s.name == name)
+    }
+
+    pub fn stale_clusters(&self) -> Vec<(String, String)> {
+        // returns the active clusters whose last scan failed, with the error
+        let cache = Cache::read(&self.completion_filename);
+        self.clusters_names()
+            .into_iter()
+            .filter_map(|name| {
+                let c = cache.clusters.get(&name)?;
+                c.is_stale()
+                    .then(|| (name.clone(), c.error.clone().unwrap_or_default()))
+            })
+            .collect()
     }
 
     pub fn read_completion_file(&self) -> String {
-        let result = fs::read(self.completion_filename.clone()).expect("Error in reading the file");
-        String::from_utf8(result).unwrap()
+        Cache::read(&self.completion_filename)
+            .entries(&self.clusters_names(), &self.separator)
+            .join("\n")
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Cluster, Context};
+    use super::{Cluster, ConfigFile, Context, expand_path, expand_path_with};
     use crate::PathBuf;
     use crate::terminal::kitty::Tabcolor;
+    fn home() -> String {
+        dirs::home_dir().unwrap().display().to_string()
+    }
+
+    #[test]
+    fn test_expand_path() {
+        let lookup = |name: &str| (name == "KUBE").then(|| "/srv/kube".to_string());
+        let expand = |path: &str| expand_path_with(path, lookup);
+        assert_eq!(expand_path("~"), home());
+        assert_eq!(expand_path("~/.kube"), format!("{}/.kube", home()));
+        assert_eq!(expand("/data/~/x"), "/data/~/x");
+        assert_eq!(expand("$KUBE/prod"), "/srv/kube/prod");
+        assert_eq!(expand("${KUBE}_old"), "/srv/kube_old");
+        assert_eq!(expand("${UNSET:-/tmp}/ktk"), "/tmp/ktk");
+        assert_eq!(expand("$UNSET/ktk"), "/ktk");
+        assert_eq!(expand("price$"), "price$");
+    }
+
     #[test]
     fn test_new() {
         let path = PathBuf::from("./conf/config.sample.yaml");
         let conf = Context::new(&path, false);
-        assert_eq!(conf.kubetmp, "/run/user/1000/.kubeconfig");
+        let runtime = std::env::var("XDG_RUNTIME_DIR")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .unwrap_or("/tmp".to_string());
+        assert_eq!(conf.kubetmp, format!("{runtime}/.kubeconfig"));
         assert_eq!(conf.maxage, 86400);
         assert_eq!(conf.clusters[0].name, "other");
-        assert_eq!(conf.clusters[1].workdir, "~/deploy/deploy_env_prod");
+        assert_eq!(
+            conf.clusters[1].workdir,
+            format!("{}/deploy/deploy_env_prod", home())
+        );
         assert_eq!(conf.clusters[1].tabcolor.active_bg, "#ff821d");
     }
 
+    #[test]
+    fn test_parse_unknown_key() {
+        let source = "global:\n  completion:\n    maxage: 10\n    flie: x\n";
+        let e = ConfigFile::parse(source).unwrap_err();
+        assert_eq!(e.line, Some(4));
+        assert_eq!(e.column, Some(5));
+        assert_eq!(e.path, Some("global.completion.flie".to_string()));
+        assert!(e.message.starts_with("unknown field `flie`"));
+    }
+
+    #[test]
+    fn test_parse_wrong_type() {
+        let source = "clusters:\n  - name: a\n    disabled: maybe\n";
+        let e = ConfigFile::parse(source).unwrap_err();
+        assert_eq!(e.line, Some(3));
+        assert_eq!(e.path, Some("clusters[0].disabled".to_string()));
+    }
+
+    #[test]
+    fn test_parse_error_in_later_cluster() {
+        let source = "clusters:
+  - name: a
+    disabled: false
+    kubeconfig:
+      file: a
+  # b
+  - name: b
+    kubeconfig:
+      file: b
+  -   name: c
+      kubeconfig:
+        path: /k
+        flie: c
+  - name: d
+    disabled: maybe
+";
+        let e = ConfigFile::parse(source).unwrap_err();
+        assert_eq!(e.path, Some("clusters[2].kubeconfig.flie".to_string()));
+        assert_eq!((e.line, e.column), (Some(13), Some(9)));
+        let source = source.replace("flie", "file");
+        let e = ConfigFile::parse(&source).unwrap_err();
+        assert_eq!(e.path, Some("clusters[3].disabled".to_string()));
+        assert_eq!((e.line, e.column), (Some(15), Some(5)));
+    }
+
+    #[test]
+    fn test_parse_anchors() {
+        let source = ".kc: &kc\n  path: /k\nclusters:\n  - name: a\n    kubeconfig:\n      <<: *kc\n      file: a\n";
+        let cfg = ConfigFile::parse(source).unwrap();
+        assert_eq!(cfg.clusters[0].kubeconfig.path, Some("/k".to_string()));
+        assert_eq!(cfg.clusters[0].kubeconfig.file, Some("a".to_string()));
+    }
+
+    #[test]
+    fn test_warnings() {
+        let source = "global:\n  gradient:\n    name: nope\nclusters:\n  - name: a\n  - name: a\n";
+        let cfg = ConfigFile::parse(source).unwrap();
+        assert_eq!(cfg.warnings().len(), 2);
+        let source = "clusters:\n  - name: a\nsessions:\n  - name: s\n    tabs:\n      - namespace: x\n        cluster: b\n  - name: s\n";
+        let cfg = ConfigFile::parse(source).unwrap();
+        assert_eq!(
+            cfg.warnings(),
+            vec![
+                "sessions[1].name: duplicate session name `s`, only the first one is used",
+                "sessions[1].tabs: empty session",
+            ]
+        );
+        let conf = Context::from_config(cfg, &PathBuf::from("ktk.yaml"), false);
+        assert_eq!(
+            conf.warnings(),
+            vec!["sessions[0].tabs[0].cluster: unknown cluster `b`"]
+        );
+    }
+
+    #[test]
+    fn test_all_contexts() {
+        let source = "clusters:
+  - name: lab
+    kubeconfig:
+      path: ./tests
+      file: kubeconfig-token.yaml
+      all-contexts: true
+  - name: ro
+    kubeconfig:
+      path: ./tests
+      file: kubeconfig-token.yaml
+      context: lab-basic
+";
+        let cfg = ConfigFile::parse(source).unwrap();
+        let conf = Context::from_config(cfg, &PathBuf::from("ktk.yaml"), false);
+        let clusters: Vec<(&str, Option<&str>)> = conf
+            .clusters
+            .iter()
+            .map(|c| (c.name.as_str(), c.context.as_deref()))
+            .collect();
+        assert_eq!(
+            clusters,
+            vec![
+                ("lab-lab", Some("lab")),
+                ("lab-lab-basic", Some("lab-basic")),
+                ("ro", Some("lab-basic")),
+            ]
+        );
+        assert_ne!(conf.clusters[0].tabcolor, conf.clusters[1].tabcolor);
+
+        // A context whose cluster name has the separator is skipped
+        let source = format!("global:\n  separator: \"-b\"\n{source}");
+        let cfg = ConfigFile::parse(&source).unwrap();
+        assert_eq!(
+            cfg.warnings(),
+            vec![
+                "clusters[0].kubeconfig.all-contexts: context `lab-basic` is ignored, the name `lab-lab-basic` contains the separator `-b`"
+            ]
+        );
+        let conf = Context::from_config(cfg, &PathBuf::from("ktk.yaml"), false);
+        let names: Vec<&str> = conf.clusters.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["lab-lab", "ro"]);
+    }
+
+    #[test]
+    fn test_tabcolor() {
+        let source = "global:
+  elevated-color: \"#ff0000\"
+clusters:
+  - name: lab
+    kubeconfig:
+      path: ./tests
+      file: kubeconfig-token.yaml
+      elevated-users: [admin]
+  - name: ro
+    kubeconfig:
+      path: ./tests
+      file: kubeconfig-token.yaml
+      default-user: basic
+      elevated-users: [admin, ci]
+  - name: as
+    kubeconfig:
+      path: ./tests
+      file: kubeconfig-token.yaml
+      impersonate: viewer
+";
+        let cfg = ConfigFile::parse(source).unwrap();
+        let conf = Context::from_config(cfg, &PathBuf::from("ktk.yaml"), false);
+        assert_eq!(conf.elevated_tabcolor.active_bg, "#ff0000");
+        // The default user of lab is admin, the user of its context
+        let lab = conf.cluster_named("lab").unwrap();
+        assert_eq!(lab.default_user, Some("admin".to_string()));
+        assert_eq!(conf.tabcolor(lab, None), lab.tabcolor);
+        assert_eq!(conf.tabcolor(lab, Some("admin")), lab.tabcolor);
+        assert_eq!(conf.tabcolor(lab, Some("basic")), lab.tabcolor);
+        let ro = conf.cluster_named("ro").unwrap();
+        assert_eq!(ro.default_user, Some("basic".to_string()));
+        assert_eq!(conf.tabcolor(ro, Some("basic")), ro.tabcolor);
+        assert_eq!(conf.tabcolor(ro, Some("admin")), conf.elevated_tabcolor);
+        assert_eq!(conf.tabcolor(ro, Some("ci")), conf.elevated_tabcolor);
+        // The impersonation is dropped even for the default user
+        let as_viewer = conf.cluster_named("as").unwrap();
+        assert_eq!(as_viewer.default_user, None);
+        assert_eq!(conf.tabcolor(as_viewer, None), as_viewer.tabcolor);
+        assert_eq!(
+            conf.tabcolor(as_viewer, Some("admin")),
+            conf.elevated_tabcolor
+        );
+    }
+
+    #[test]
+    fn test_protected() {
+        let source = "clusters:
+  - name: prod
+    protected: true
+    kubeconfig:
+      impersonate: viewer
+  - name: dev
+";
+        let cfg = ConfigFile::parse(source).unwrap();
+        let conf = Context::from_config(cfg, &PathBuf::from("ktk.yaml"), false);
+        let prod = conf.cluster_named("prod").unwrap();
+        assert!(prod.protected);
+        assert_eq!(prod.impersonate, Some("viewer".to_string()));
+        assert_eq!(prod.tabcolor.active_bg, "#8700af");
+        assert_eq!(prod.tabcolor.active_fg, "#FFFFFF");
+        assert_ne!(prod.tabcolor, conf.elevated_tabcolor);
+        assert!(!conf.cluster_named("dev").unwrap().protected);
+    }
+
+    #[test]
+    fn test_discovery() {
+        let source = "clusters:
+  - name: mine
+    kubeconfig:
+      path: ./tests
+      file: kubeconfig-token.yaml
+discovery:
+  paths:
+    - ./tests/kubeconfig-*.yaml
+  workdir:
+    path: /srv/{cluster}
+  maxage: 60
+favorites:
+  - namespace: web
+    cluster: alice@corp
+";
+        let cfg = ConfigFile::parse(source).unwrap();
+        assert!(cfg.warnings().is_empty());
+        let conf = Context::from_config(cfg, &PathBuf::from("ktk.yaml"), false);
+        assert!(conf.warnings().is_empty());
+        let clusters: Vec<(&str, Option<&str>)> = conf
+            .clusters
+            .iter()
+            .map(|c| (c.name.as_str(), c.context.as_deref()))
+            .collect();
+        assert_eq!(
+            clusters,
+            vec![
+                ("mine", None),
+                (
+                    "arn:aws:eks:eu-west-1:123456789012:cluster/prod",
+                    Some("arn:aws:eks:eu-west-1:123456789012:cluster/prod")
+                ),
+                (
+                    "gke_my-project_europe-west1_staging",
+                    Some("gke_my-project_europe-west1_staging")
+                ),
+                ("alice@corp", Some("alice@corp")),
+                ("lab-basic", Some("lab-basic")),
+            ]
+        );
+        let corp = conf.cluster_named("alice@corp").unwrap();
+        assert!(
+            corp.kubeconfig_path
+                .ends_with("/tests/kubeconfig-oidc.yaml")
+        );
+        assert_eq!(corp.workdir, "/srv/alice@corp/");
+        assert_eq!(corp.maxage, 60);
+
+        // A discovered name with the separator could not be chosen
+        let source = format!("global:\n  separator: \":\"\n{source}");
+        let cfg = ConfigFile::parse(&source).unwrap();
+        let conf = Context::from_config(cfg, &PathBuf::from("ktk.yaml"), false);
+        assert_eq!(
+            conf.clusters_names(),
+            vec![
+                "mine",
+                "gke_my-project_europe-west1_staging",
+                "alice@corp",
+                "lab-basic"
+            ]
+        );
+    }
+
     #[test]
     fn test_clusters_name() {
         let path = PathBuf::from("./conf/config.sample.yaml");
@@ -425,11 +1478,19 @@ mod tests {
             conf.cluster_named("prod"),
             Some(&Cluster {
                 name: "prod".to_string(),
-                kubeconfig_path: "~/.kube/konfigs/prod".to_string(),
-                workdir: "~/deploy/deploy_env_prod".to_string(),
+                kubeconfig_path: format!("{}/.kube/konfigs/prod", home()),
+                context: None,
+                default_user: None,
+                impersonate: None,
+                elevated_users: Vec::new(),
+                protected: false,
+                workdir: format!("{}/deploy/deploy_env_prod", home()),
                 prefixns: "".to_string(),
                 disabled: false,
+                maxage: 86400,
                 timeout: 5,
+                get_timeout: 3,
+                connect_timeout: 800,
                 tabcolor: Tabcolor {
                     active_bg: "#ff821d".to_string(),
                     inactive_bg: "NONE".to_string(),
@@ -439,6 +1500,6 @@ mod tests {
             })
         );
         let c1 = conf.cluster_named("dev").unwrap();
-        assert_eq!(c1.workdir, "~/deploy/deploy_env_dev");
+        assert_eq!(c1.workdir, format!("{}/deploy/deploy_env_dev", home()));
     }
 }
@@ -0,0 +1,184 @@
+//! Clusters discovered in kubeconfig files, added to the clusters of the
+//! config file
+use crate::config::{ClusterConfig, DiscoveryConfig, KubeconfigConfig, WorkdirConfig, expand_path};
+use crate::kubeconfig::Kubeconfig;
+use log::{debug, info, warn};
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Entries of a folder, sorted, without the hidden ones
+fn entries(dir: &Path) -> Vec<PathBuf> {
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let mut entries: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(rd) => rd.flatten().map(|e| e.path()).collect(),
+        Err(_) => return Vec::new(),
+    };
+    entries.retain(|p| {
+        !p.file_name()
+            .is_some_and(|n| n.to_string_lossy().starts_with('.'))
+    });
+    entries.sort();
+    entries
+}
+
+/// Paths matching a pattern with wildcards (`*`, `?`, `[...]` and `**`),
+/// like a shell, the hidden files are only matched by a leading `.`.
+pub fn glob(pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let options = glob::MatchOptions {
+        require_literal_leading_dot: true,
+        ..glob::MatchOptions::new()
+    };
+    match glob::glob_with(pattern, options) {
+        Ok(paths) => Ok(paths.flatten().collect()),
+        Err(e) => Err(format!("invalid pattern `{pattern}`: {e}")),
+    }
+}
+
+/// Kubeconfig files of the discovery, the files of the folders are taken.
+pub fn kubeconfig_files(discovery: &DiscoveryConfig) -> Vec<PathBuf> {
+    let mut patterns: Vec<String> = discovery.paths.iter().map(|p| expand_path(p)).collect();
+    if discovery.kubeconfig_env
+        && let Ok(v) = env::var("KUBECONFIG")
+    {
+        patterns.extend(v.split(':').filter(|p| !p.is_empty()).map(String::from));
+    }
+    let mut paths = Vec::new();
+    for pattern in patterns.iter() {
+        match glob(pattern) {
+            Ok(v) => paths.extend(v),
+            Err(e) => warn!("discovery: {e}"),
+        }
+    }
+    let mut files = Vec::new();
+    for path in paths {
+        let found = match path.is_dir() {
+            true => entries(&path).into_iter().filter(|p| p.is_file()).collect(),
+            false => vec![path],
+        };
+        for file in found {
+            let file = fs::canonicalize(&file).unwrap_or(file);
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+    }
+    files
+}
+
+fn canonical(path: &str) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path))
+}
+
+/// Clusters of the contexts of the discovered kubeconfig files, named after
+/// their context. The contexts already used by the `declared` clusters
+/// (name, kubeconfig file, context), the ones whose name contains the
+/// `separator` and the kubeconfig files written by ktk in `kubetmp` are
+/// skipped.
+pub fn clusters(
+    discovery: &DiscoveryConfig,
+    declared: &[(String, String, Option<String>)],
+    kubetmp: &str,
+    separator: &str,
+) -> Vec<ClusterConfig> {
+    if !discovery.is_enabled() {
+        return Vec::new();
+    }
+    let declared: Vec<(&str, PathBuf, Option<&str>)> = declared
+        .iter()
+        .map(|(name, path, context)| (name.as_str(), canonical(path), context.as_deref()))
+        .collect();
+    let kubetmp = canonical(kubetmp);
+    let mut names: HashSet<String> = declared.iter().map(|d| d.0.to_string()).collect();
+    let mut clusters = Vec::new();
+    for file in kubeconfig_files(discovery) {
+        if file.starts_with(&kubetmp) {
+            continue;
+        }
+        let kcf = match Kubeconfig::new(file.display().to_string()) {
+            Ok(v) if v.ktk_cluster().is_none() => v,
+            Ok(_) => continue,
+            Err(e) => {
+                debug!("{} is not a kubeconfig file: {e}", file.display());
+                continue;
+            }
+        };
+        let current = kcf.current_context();
+        for context in kcf.context_names() {
+            let used = declared.iter().any(|(_, path, c)| {
+                *path == file
+                    && (*c == Some(&context) || (c.is_none() && current == Some(context.clone())))
+            });
+            if used {
+                continue;
+            }
+            // The name could not be split from the namespace in the search
+            if context.contains(separator) {
+                warn!(
+                    "cluster {context} of {} is ignored, the name contains the separator {separator}",
+                    file.display()
+                );
+                continue;
+            }
+            if !names.insert(context.clone()) {
+                info!(
+                    "cluster {context} of {} is ignored, the name is already used",
+                    file.display()
+                );
+                continue;
+            }
+            let workdir = discovery.workdir.clone();
+            clusters.push(ClusterConfig {
+                name: context.clone(),
+                disabled: false,
+                protected: false,
+                maxage: discovery.maxage,
+                workdir: WorkdirConfig {
+                    path: workdir.path.map(|p| p.replace("{cluster}", &context)),
+                    subdir: workdir.subdir.map(|p| p.replace("{cluster}", &context)),
+                    prefixns: workdir.prefixns,
+                },
+                kubeconfig: KubeconfigConfig {
+                    path: file.parent().map(|p| p.display().to_string()),
+                    file: file.file_name().map(|f| f.to_string_lossy().to_string()),
+                    context: Some(context),
+                    ..KubeconfigConfig::default()
+                },
+            });
+        }
+    }
+    clusters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_glob() {
+        assert_eq!(
+            glob("tests/kubeconfig-[eg]*.y?ml").unwrap(),
+            vec![
+                PathBuf::from("tests/kubeconfig-eks.yaml"),
+                PathBuf::from("tests/kubeconfig-gke.yaml"),
+            ]
+        );
+        assert_eq!(
+            glob("tests/kube[!x]onfig-eks.yaml").unwrap(),
+            vec![PathBuf::from("tests/kubeconfig-eks.yaml")]
+        );
+        assert!(
+            glob("tests/**/kubeconfig-oidc.yaml")
+                .unwrap()
+                .contains(&PathBuf::from("tests/kubeconfig-oidc.yaml"))
+        );
+        assert!(glob("tests/nothing-*").unwrap().is_empty());
+        assert!(glob("tests/kubeconfig-[eg.yaml").is_err());
+    }
+}
@@ -0,0 +1,205 @@
+//! Edition of the favorites of the ktk yaml file, the file is edited as
+//! text so that its comments and anchors are kept
+use crate::config::{ConfigFile, Favorite};
+
+const HEADER: &str = "favorites:";
+
+// The line has a value, it is neither empty nor a comment
+fn is_content(line: &str) -> bool {
+    let t = line.trim_start();
+    !t.is_empty() && !t.starts_with('#')
+}
+
+fn indent(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// Position of the `favorites` list in the lines of the file.
+#[derive(Debug, PartialEq)]
+struct Block {
+    header: usize,      // line of the `favorites:` key
+    items: Vec<usize>,  // first line of each item
+    end: usize,         // line following the last item
+    indent: usize,      // indentation of the items
+    inline_empty: bool, // the list is written `favorites: []`
+}
+
+fn find_block(lines: &[&str]) -> Result<Option<Block>, String> {
+    let Some(header) = lines.iter().position(|l| l.starts_with(HEADER)) else {
+        return Ok(None);
+    };
+    let rest = lines[header][HEADER.len()..].trim();
+    let inline_empty = rest.starts_with("[]");
+    if !inline_empty && is_content(rest) {
+        return Err("favorites must be written as a block list to be edited".to_string());
+    }
+    let mut block = Block {
+        header,
+        items: Vec::new(),
+        end: header + 1,
+        indent: 2,
+        inline_empty,
+    };
+    if inline_empty {
+        return Ok(Some(block));
+    }
+    let mut item_indent = None;
+    for (i, line) in lines.iter().enumerate().skip(header + 1) {
+        if !is_content(line) {
+            continue;
+        }
+        let is_item = line.trim_start().starts_with('-');
+        let indent = indent(line);
+        match item_indent {
+            None if indent == 0 && !is_item => break,
+            None => item_indent = Some(indent),
+            Some(n) if indent < n || (indent == n && !is_item) => break,
+            Some(_) => {}
+        }
+        if is_item && Some(indent) == item_indent {
+            block.items.push(i);
+        }
+        block.end = i + 1;
+    }
+    block.indent = item_indent.unwrap_or(2);
+    Ok(Some(block))
+}
+
+// yaml representation of a scalar, quoted if needed
+fn scalar(value: &str) -> String {
+    serde_yaml::to_string(value)
+        .unwrap_or_default()
+        .trim_end()
+        .to_string()
+}
+
+fn item_lines(fav: &Favorite, indent: usize) -> Vec<String> {
+    let pad = " ".repeat(indent);
+    let mut lines = vec![
+        format!("{pad}- namespace: {}", scalar(&fav.namespace)),
+        format!("{pad}  cluster: {}", scalar(&fav.cluster)),
+    ];
+    if let Some(alias) = &fav.alias {
+        lines.push(format!("{pad}  alias: {}", scalar(alias)));
+    }
+    lines
+}
+
+fn join(lines: Vec<String>) -> String {
+    let mut result = lines.join("\n");
+    result.push('\n');
+    result
+}
+
+// The edited file must be valid and have the expected favorites
+fn check(source: String, expected: &[Favorite]) -> Result<String, String> {
+    match ConfigFile::parse(&source) {
+        Ok(cfg) if cfg.favorites == expected => Ok(source),
+        Ok(_) => Err("unable to edit the favorites of the file".to_string()),
+        Err(e) => Err(format!("unable to edit the favorites of the file: {e}")),
+    }
+}
+
+/// Returns the source of the config file with a new favorite.
+pub fn add(source: &str, current: &[Favorite], fav: &Favorite) -> Result<String, String> {
+    let lines: Vec<&str> = source.lines().collect();
+    let mut result: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+    match find_block(&lines)? {
+        None => {
+            result.push(String::new());
+            result.push(HEADER.to_string());
+            result.extend(item_lines(fav, 2));
+        }
+        Some(block) => {
+            if block.inline_empty {
+                result[block.header] = HEADER.to_string();
+            }
+            let new = item_lines(fav, block.indent);
+            result.splice(block.end..block.end, new);
+        }
+    }
+    let mut expected = current.to_vec();
+    expected.push(fav.clone());
+    check(join(result), &expected)
+}
+
+/// Returns the source of the config file without the favorite `index`.
+pub fn remove(source: &str, current: &[Favorite], index: usize) -> Result<String, String> {
+    let lines: Vec<&str> = source.lines().collect();
+    let block = match find_block(&lines)? {
+        Some(b) if b.items.len() == current.len() && index < current.len() => b,
+        _ => return Err("unable to find the favorite in the file".to_string()),
+    };
+    let start = block.items[index];
+    let end = block.items.get(index + 1).copied().unwrap_or(block.end);
+    let mut result: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+    result.drain(start..end);
+    if block.items.len() == 1 {
+        result[block.header] = format!("{HEADER} []");
+    }
+    let mut expected = current.to_vec();
+    expected.remove(index);
+    check(join(result), &expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{add, remove};
+    use crate::config::{ConfigFile, Favorite};
+
+    const SOURCE: &str = "# ktk
+global:
+  separator: \"::\"
+favorites:
+  # payments
+  - namespace: payments
+    cluster: prod
+    alias: pay
+  - namespace: kube-system
+    cluster: dev
+
+clusters:
+  - name: prod
+";
+
+    fn fav(namespace: &str, cluster: &str, alias: Option<&str>) -> Favorite {
+        Favorite {
+            namespace: namespace.to_string(),
+            cluster: cluster.to_string(),
+            alias: alias.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_add() {
+        let current = ConfigFile::parse(SOURCE).unwrap().favorites;
+        let result = add(SOURCE, &current, &fav("web", "prod", Some("w"))).unwrap();
+        assert!(result.contains(
+            "    cluster: dev\n  - namespace: web\n    cluster: prod\n    alias: w\n\nclusters:"
+        ));
+        assert!(result.starts_with("# ktk\n"));
+    }
+
+    #[test]
+    fn test_add_without_favorites() {
+        let source = "clusters:\n- name: prod\n";
+        let result = add(source, &[], &fav("web", "prod", None)).unwrap();
+        assert_eq!(
+            result,
+            "clusters:\n- name: prod\n\nfavorites:\n  - namespace: web\n    cluster: prod\n"
+        );
+    }
+
+    #[test]
+    fn test_remove() {
+        let current = ConfigFile::parse(SOURCE).unwrap().favorites;
+        let result = remove(SOURCE, &current, 0).unwrap();
+        assert!(result.contains("favorites:\n  # payments\n  - namespace: kube-system\n"));
+        let result = remove(&result, &current[1..], 0).unwrap();
+        assert!(result.contains("favorites: []\n  # payments\n\nclusters:"));
+        let result = add(&result, &[], &fav("web", "prod", None)).unwrap();
+        assert!(
+            result.contains("favorites:\n  - namespace: web\n    cluster: prod\n  # payments\n")
+        );
+    }
+}
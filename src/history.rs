@@ -0,0 +1,233 @@
+//! History of the selected namespaces, the candidates of the search are
+//! ranked by frecency (frequency and recency of the selections)
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use log::{info, warn};
+
+use crate::cache::{CacheLock, now};
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct History {
+    #[serde(default)]
+    pub entries: Vec<HistoryEntry>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub namespace: String,
+    pub cluster: String,
+    pub count: u64,       // number of selections
+    pub visits: Vec<u64>, // time of the last selections, the most recent last
+}
+
+// Only the last visits are kept to compute the recency
+const MAX_VISITS: usize = 10;
+// An entry not selected for this time is forgotten
+const MAX_AGE: u64 = 90 * 86400;
+
+// Weight of a visit according to its age
+fn weight(age: u64) -> f64 {
+    match age {
+        a if a < 3600 => 100.0,
+        a if a < 86400 => 80.0,
+        a if a < 7 * 86400 => 60.0,
+        a if a < 30 * 86400 => 40.0,
+        _ => 20.0,
+    }
+}
+
+impl HistoryEntry {
+    pub fn score(&self, now: u64) -> f64 {
+        if self.visits.is_empty() {
+            return 0.0;
+        }
+        let recency: f64 = self
+            .visits
+            .iter()
+            .map(|t| weight(now.saturating_sub(*t)))
+            .sum();
+        recency * self.count as f64 / self.visits.len() as f64
+    }
+
+    fn last_visit(&self) -> u64 {
+        self.visits.last().copied().unwrap_or(0)
+    }
+}
+
+impl History {
+    pub fn read(path: &str) -> History {
+        let content = match fs::read(path) {
+            Ok(v) => v,
+            Err(_) => return History::default(),
+        };
+        match serde_json::from_slice(&content) {
+            Ok(v) => v,
+            Err(e) => {
+                info!("history {path} is ignored: {e}");
+                History::default()
+            }
+        }
+    }
+
+    pub fn write(&self, path: &str) {
+        if let Some(parent) = Path::new(path).parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        let tmp = format!("{path}.{}.tmp", std::process::id());
+        let content = serde_json::to_vec(self).expect("Couldn't serialize history");
+        if let Err(e) = fs::write(&tmp, content).and_then(|_| fs::rename(&tmp, path)) {
+            warn!("Couldn't write history {path}: {e}");
+        }
+    }
+
+    // Record the selections in the history file, it is read again under a
+    // lock so that the visits recorded meanwhile by another ktk are kept
+    pub fn save_visits(path: &str, visits: &[(String, String)]) {
+        if visits.is_empty() {
+            return;
+        }
+        let _lock = CacheLock::wait(path);
+        let mut history = History::read(path);
+        for (namespace, cluster) in visits {
+            history.record(namespace, cluster);
+        }
+        history.write(path);
+    }
+
+    // Record the selection of a namespace, the old entries are forgotten
+    pub fn record(&mut self, namespace: &str, cluster: &str) {
+        let now = now();
+        match self
+            .entries
+            .iter_mut()
+            .find(|e| e.namespace == namespace && e.cluster == cluster)
+        {
+            Some(entry) => {
+                entry.count += 1;
+                entry.visits.push(now);
+                let extra = entry.visits.len().saturating_sub(MAX_VISITS);
+                entry.visits.drain(..extra);
+            }
+            None => self.entries.push(HistoryEntry {
+                namespace: namespace.to_string(),
+                cluster: cluster.to_string(),
+                count: 1,
+                visits: vec![now],
+            }),
+        }
+        self.entries
+            .retain(|e| now.saturating_sub(e.last_visit()) < MAX_AGE);
+    }
+
+    // Sort the `namespace<sep>cluster` entries, the most frecent first.
+    // The order of the entries never selected is kept.
+    pub fn rank(&self, entries: &mut [String], sep: &str) {
+        if self.entries.is_empty() {
+            return;
+        }
+        let now = now();
+        let scores: HashMap<(&str, &str), f64> = self
+            .entries
+            .iter()
+            .map(|e| ((e.namespace.as_str(), e.cluster.as_str()), e.score(now)))
+            .collect();
+        // The scores are computed once, not at each comparison
+        let mut ranked: Vec<(f64, String)> = entries
+            .iter_mut()
+            .map(|entry| {
+                let score = entry
+                    .split_once(sep)
+                    .and_then(|key| scores.get(&key).copied())
+                    .unwrap_or(0.0);
+                (score, std::mem::take(entry))
+            })
+            .collect();
+        ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+        for (entry, (_, ranked)) in entries.iter_mut().zip(ranked) {
+            *entry = ranked;
+        }
+    }
+
+    // Returns the `namespace<sep>cluster` entries of the given clusters
+    // that have been selected, the most frecent first
+    pub fn recent(&self, clusters: &[String], sep: &str) -> Vec<String> {
+        let mut entries: Vec<String> = self
+            .entries
+            .iter()
+            .filter(|e| clusters.contains(&e.cluster))
+            .map(|e| format!("{}{sep}{}", e.namespace, e.cluster))
+            .collect();
+        self.rank(&mut entries, sep);
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{History, HistoryEntry, MAX_AGE};
+    use crate::cache::now;
+
+    #[test]
+    fn test_rank() {
+        let mut history = History::default();
+        history.record("b", "prod");
+        history.record("b", "prod");
+        history.record("c", "dev");
+        let mut entries = vec![
+            "a::dev".to_string(),
+            "c::dev".to_string(),
+            "a::prod".to_string(),
+            "b::prod".to_string(),
+        ];
+        history.rank(&mut entries, "::");
+        assert_eq!(entries, vec!["b::prod", "c::dev", "a::dev", "a::prod"]);
+    }
+
+    #[test]
+    fn test_recency() {
+        let now = now();
+        let old = HistoryEntry {
+            count: 5,
+            visits: vec![now - 40 * 86400; 5],
+            ..Default::default()
+        };
+        let recent = HistoryEntry {
+            count: 2,
+            visits: vec![now - 60; 2],
+            ..Default::default()
+        };
+        assert!(recent.score(now) > old.score(now));
+    }
+
+    #[test]
+    fn test_recent() {
+        let mut history = History::default();
+        history.entries.push(HistoryEntry {
+            namespace: "old".to_string(),
+            cluster: "prod".to_string(),
+            count: 1,
+            visits: vec![now() - MAX_AGE - 1],
+        });
+        history.record("a", "prod");
+        history.record("a", "removed");
+        let clusters = vec!["prod".to_string()];
+        assert_eq!(history.recent(&clusters, "::"), vec!["a::prod"]);
+    }
+
+    #[test]
+    fn test_save_visits() {
+        let dir = std::env::temp_dir().join(format!("ktk-test-history-{}", std::process::id()));
+        let path = dir.join("history.json").display().to_string();
+        History::save_visits(&path, &[("a".to_string(), "prod".to_string())]);
+        // The visits of another ktk are added to the file
+        History::save_visits(&path, &[("a".to_string(), "prod".to_string())]);
+        History::save_visits(&path, &[("b".to_string(), "dev".to_string())]);
+        let history = History::read(&path);
+        assert_eq!(history.entries.len(), 2);
+        assert_eq!(history.entries[0].count, 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
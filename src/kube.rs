@@ -1,4 +1,4 @@
-use k8s_openapi::api::core::v1::Namespace;
+use k8s_openapi::api::core::v1::{Namespace, Pod, ResourceQuota};
 use kube::{
     Client, Config,
     api::{Api, ListParams},
@@ -6,161 +6,398 @@ use kube::{
 };
 use log::{info, warn};
 use skim::prelude::*;
-use std::{io::Cursor, path::Path, process, sync::mpsc, thread, time::Duration};
+use std::{
+    collections::BTreeMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::Cursor,
+    path::Path,
+    process,
+    sync::{Mutex, mpsc},
+    time::{Duration, Instant},
+};
+use tokio::sync::Semaphore;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Cluster {
-    pub name: String,            // cluster name
-    pub kubeconfig_path: String, // kubeconfig path/file
-    pub workdir: String,         // cluster working directory
-    pub prefixns: String,        // prefix before the name of the working directory
-    pub disabled: bool,          // cluster is disabled
+    pub name: String,                 // cluster name
+    pub kubeconfig_path: String,      // kubeconfig path/file
+    pub context: Option<String>,      // context of the kubeconfig, the current one by default
+    pub default_user: Option<String>, // user of the tabs, the one of the context by default
+    pub impersonate: Option<String>,  // user impersonated by the default user of the tabs
+    pub elevated_users: Vec<String>,  // users whose tabs have the elevated color
+    pub protected: bool,              // a tab is opened only after a confirmation
+    pub workdir: String,              // cluster working directory
+    pub prefixns: String,             // prefix before the name of the working directory
+    pub disabled: bool,               // cluster is disabled
+    pub maxage: u64,                  // duration of validity of the namespaces cache
     pub tabcolor: crate::terminal::kitty::Tabcolor,
-    pub timeout: u32, // maximum time to retrieve the list of namespaces
+    pub timeout: u32,         // maximum time to retrieve the list of namespaces
+    pub get_timeout: u32,     // timeout of the namespaces list request in seconds
+    pub connect_timeout: u64, // timeout of the connection to the API server in milliseconds
 }
 
-pub fn ns_workdir(cluster: &Cluster, namespace: String, kubeconfig: String) -> String {
-    if cluster.prefixns.is_empty()
-        || namespace.get(..cluster.prefixns.len()).unwrap_or("") != cluster.prefixns
-    {
-        let testpath = format!("{}/{}", cluster.workdir, namespace);
-        if Path::new(&testpath).exists() {
-            format!(
-                "export KUBECONFIG={} && cd {}/{}",
-                kubeconfig, cluster.workdir, namespace
-            )
-        } else {
-            format!("export KUBECONFIG={} && cd {}", kubeconfig, cluster.workdir)
-        }
+impl Cluster {
+    /// Hash of the settings that change the namespaces found by a scan, the
+    /// cache of a cluster is outdated when they change. The other changes
+    /// of the config file (favorites, colors...) keep the cache.
+    pub fn scan_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        (&self.kubeconfig_path, &self.context).hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+/// Returns the working directory of a namespace, the directory of the
+/// namespace (without the prefix of the cluster) if it exists, otherwise
+/// the working directory of the cluster.
+pub fn ns_dir(cluster: &Cluster, namespace: &str) -> String {
+    let nsdir = match namespace.strip_prefix(cluster.prefixns.as_str()) {
+        Some(v) if !cluster.prefixns.is_empty() => v,
+        _ => namespace,
+    };
+    let testpath = format!("{}/{}", cluster.workdir, nsdir);
+    if Path::new(&testpath).exists() {
+        testpath
     } else {
-        let nsdir = namespace.get(cluster.prefixns.len()..).unwrap();
-        let testpath = format!("{}/{}", cluster.workdir, nsdir);
-        if Path::new(&testpath).exists() {
-            format!(
-                "export KUBECONFIG={} && cd {}/{}",
-                kubeconfig, cluster.workdir, nsdir
-            )
-        } else {
-            format!("export KUBECONFIG={kubeconfig} && cd {}", cluster.workdir)
-        }
+        cluster.workdir.clone()
     }
 }
 
-pub fn get_kubeconfig_option(kubeconfig: Kubeconfig) -> Option<KubeConfigOptions> {
-    for ct in kubeconfig.contexts.iter() {
-        if Some(ct.name.clone()) == kubeconfig.current_context {
-            match ct.context.clone() {
-                Some(context) => {
-                    return Some(KubeConfigOptions {
-                        context: kubeconfig.current_context,
-                        cluster: Some(context.cluster),
-                        user: context.user,
-                    });
-                }
-                None => return None,
+pub fn ns_workdir(cluster: &Cluster, namespace: String, kubeconfig: String) -> String {
+    format!(
+        "export KUBECONFIG={kubeconfig} && cd {}",
+        ns_dir(cluster, &namespace)
+    )
+}
+
+/// Options to connect to the context `context` of the kubeconfig, its
+/// current context by default.
+pub fn get_kubeconfig_option(
+    kubeconfig: Kubeconfig,
+    context: Option<&str>,
+) -> Option<KubeConfigOptions> {
+    let name = context.map(String::from).or(kubeconfig.current_context)?;
+    let ct = kubeconfig.contexts.into_iter().find(|c| c.name == name)?;
+    let context = ct.context?;
+    Some(KubeConfigOptions {
+        context: Some(name),
+        cluster: Some(context.cluster),
+        user: context.user,
+    })
+}
+
+pub async fn cluster_client(cluster: &Cluster) -> Result<Client, String> {
+    // The kubeconfig file is read on a blocking thread of the runtime
+    let path = cluster.kubeconfig_path.clone();
+    let kubeconfig = tokio::task::spawn_blocking(move || Kubeconfig::read_from(path))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
+    let kubeopt = get_kubeconfig_option(kubeconfig.clone(), cluster.context.as_deref()).ok_or(
+        match &cluster.context {
+            Some(c) => format!("{} has no context {c}", cluster.name),
+            None => format!("{} has no current context", cluster.name),
+        },
+    )?;
+    let mut config = Config::from_custom_kubeconfig(kubeconfig, &kubeopt)
+        .await
+        .map_err(|e| e.to_string())?;
+    config.connect_timeout = Some(Duration::from_millis(cluster.connect_timeout));
+    config.read_timeout = Some(Duration::from_secs(cluster.get_timeout.into()));
+    Client::try_from(config).map_err(|e| e.to_string())
+}
+
+async fn list_namespaces(cluster: &Cluster) -> Result<Vec<String>, String> {
+    let client = cluster_client(cluster).await?;
+    let namespaces: Api<Namespace> = Api::all(client);
+
+    let lp = ListParams::default()
+        .timeout(cluster.get_timeout)
+        .match_any();
+    match namespaces.list(&lp).await {
+        Ok(n) => Ok(n
+            .items
+            .iter()
+            .filter_map(|ns| ns.metadata.name.clone())
+            .collect()),
+        Err(e) => Err(format!("{} is unreachable: {e}", cluster.name)),
+    }
+}
+
+pub async fn get_namespaces(cluster: &Cluster) -> Result<Vec<String>, String> {
+    let deadline = Duration::from_secs(cluster.timeout.into());
+    match tokio::time::timeout(deadline, list_namespaces(cluster)).await {
+        Ok(ns) => ns,
+        Err(_) => Err(format!(
+            "{} did not answer within {}s",
+            cluster.name, cluster.timeout
+        )),
+    }
+}
+
+// Human readable age, like kubectl
+pub fn human_age(secs: i64) -> String {
+    match secs {
+        s if s < 120 => format!("{s}s"),
+        s if s < 7200 => format!("{}m", s / 60),
+        s if s < 172800 => format!("{}h", s / 3600),
+        s => format!("{}d", s / 86400),
+    }
+}
+
+// Values longer than that are truncated in the preview
+const PREVIEW_VALUE_MAXLEN: usize = 80;
+
+fn preview_value(value: &str) -> String {
+    match value.char_indices().nth(PREVIEW_VALUE_MAXLEN) {
+        Some((i, _)) => format!("{}…", &value[..i]),
+        None => value.to_string(),
+    }
+}
+
+/// Text of the preview pane for a namespace.
+pub fn format_details(
+    cluster: &str,
+    ns: &Namespace,
+    quotas: &[ResourceQuota],
+    pods: &[Pod],
+) -> String {
+    let mut out = String::new();
+    let name = ns.metadata.name.clone().unwrap_or_default();
+    out.push_str(&format!("namespace   {name}\n"));
+    out.push_str(&format!("cluster     {cluster}\n"));
+    let phase = ns.status.as_ref().and_then(|s| s.phase.clone());
+    out.push_str(&format!(
+        "phase       {}\n",
+        phase.unwrap_or("Unknown".to_string())
+    ));
+    if let Some(t) = &ns.metadata.creation_timestamp {
+        let age = (k8s_openapi::chrono::Utc::now() - t.0).num_seconds();
+        out.push_str(&format!("age         {}\n", human_age(age)));
+    }
+
+    let mut phases: BTreeMap<String, usize> = BTreeMap::new();
+    for pod in pods {
+        let phase = pod.status.as_ref().and_then(|s| s.phase.clone());
+        *phases
+            .entry(phase.unwrap_or("Unknown".to_string()))
+            .or_default() += 1;
+    }
+    let detail: Vec<String> = phases.iter().map(|(p, n)| format!("{p} {n}")).collect();
+    match detail.is_empty() {
+        true => out.push_str("pods        0\n"),
+        false => out.push_str(&format!(
+            "pods        {} ({})\n",
+            pods.len(),
+            detail.join(", ")
+        )),
+    }
+
+    let sections = [
+        ("labels", ns.metadata.labels.clone()),
+        ("annotations", ns.metadata.annotations.clone()),
+    ];
+    for (title, values) in sections {
+        let values = values.unwrap_or_default();
+        if !values.is_empty() {
+            out.push_str(&format!("\n{title}\n"));
+            for (k, v) in values {
+                out.push_str(&format!("  {k}={}\n", preview_value(&v)));
             }
         }
     }
-    None
+
+    for quota in quotas {
+        let status = quota.status.clone().unwrap_or_default();
+        let hard = status.hard.unwrap_or_default();
+        let used = status.used.unwrap_or_default();
+        out.push_str(&format!(
+            "\nquota {}\n",
+            quota.metadata.name.clone().unwrap_or_default()
+        ));
+        for (resource, limit) in hard {
+            let current = used.get(&resource).map(|q| q.0.as_str()).unwrap_or("0");
+            out.push_str(&format!("  {resource:<24} {current}/{}\n", limit.0));
+        }
+    }
+    out
 }
 
-#[tokio::main]
-pub async fn get_namespaces(kubeconfig: Kubeconfig, sep: String, timeout: u32) -> Vec<String> {
-    match get_kubeconfig_option(kubeconfig.clone()) {
-        Some(kubeopt) => {
-            let config = match Config::from_custom_kubeconfig(kubeconfig, &kubeopt).await {
-                Ok(mut c) => {
-                    c.connect_timeout = Some(Duration::from_secs(timeout.into()));
-                    c
-                }
-                Err(e) => {
-                    warn!("{}", e);
-                    return Vec::new();
-                }
-            };
-            let client = match Client::try_from(config) {
-                Ok(c) => c,
-                Err(e) => {
-                    warn!("{}", e);
-                    return Vec::new();
-                }
-            };
-            let namespaces: Api<Namespace> = Api::all(client);
+async fn namespace_details(cluster: &Cluster, namespace: &str) -> Result<String, String> {
+    let client = cluster_client(cluster).await?;
+    let namespaces: Api<Namespace> = Api::all(client.clone());
+    let quotas: Api<ResourceQuota> = Api::namespaced(client.clone(), namespace);
+    let pods: Api<Pod> = Api::namespaced(client, namespace);
+    let lp = ListParams::default().timeout(cluster.get_timeout);
+    let (ns, quotas, pods) =
+        tokio::try_join!(namespaces.get(namespace), quotas.list(&lp), pods.list(&lp))
+            .map_err(|e| format!("{} is unreachable: {e}", cluster.name))?;
+    Ok(format_details(
+        &cluster.name,
+        &ns,
+        &quotas.items,
+        &pods.items,
+    ))
+}
+
+/// Scanner of the clusters, all the requests share a single runtime
+/// and at most `max_parallel` clusters are requested at the same time.
+pub struct Scanner {
+    runtime: Option<tokio::runtime::Runtime>,
+    max_parallel: usize,
+}
+
+impl Scanner {
+    pub fn new(max_parallel: usize) -> Scanner {
+        let max_parallel = max_parallel.max(1);
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(max_parallel.min(4))
+            .max_blocking_threads(max_parallel)
+            .enable_all()
+            .build()
+            .expect("Unable to start the tokio runtime");
+        Scanner {
+            runtime: Some(runtime),
+            max_parallel,
+        }
+    }
+
+    fn runtime(&self) -> &tokio::runtime::Runtime {
+        self.runtime.as_ref().unwrap()
+    }
 
-            let cluster_name = match kubeopt.cluster {
-                Some(c) => c,
-                None => return Vec::new(),
+    /// Details of a namespace for the preview pane of the fuzzy finder.
+    pub fn namespace_details(&self, cluster: &Cluster, namespace: &str) -> Result<String, String> {
+        let deadline = Duration::from_secs(cluster.timeout.into());
+        let details = namespace_details(cluster, namespace);
+        self.runtime().block_on(async {
+            tokio::time::timeout(deadline, details)
+                .await
+                .unwrap_or_else(|_| {
+                    Err(format!(
+                        "{} did not answer within {}s",
+                        cluster.name, cluster.timeout
+                    ))
+                })
+        })
+    }
+
+    /// Scan the namespaces of all the active clusters, the result of each
+    /// cluster is given to `on_result` as soon as it answers.
+    pub fn get_all_ns<F>(
+        &self,
+        clusters: Vec<Cluster>,
+        scan_timeout: Option<Duration>,
+        mut on_result: F,
+    ) where
+        F: FnMut(&str, Result<Vec<String>, String>),
+    {
+        let (tx, rx) = mpsc::channel();
+        let semaphore = Arc::new(Semaphore::new(self.max_parallel));
+        let mut pending = Vec::new();
+        let mut tasks = Vec::new();
+        let start = Instant::now();
+        for cl in clusters {
+            if !cl.disabled {
+                pending.push(cl.name.clone());
+                let tx1 = tx.clone();
+                let semaphore = semaphore.clone();
+                tasks.push(self.runtime().spawn(async move {
+                    // The timeout of the cluster starts when it is requested
+                    let _permit = semaphore.acquire_owned().await;
+                    let ns = get_namespaces(&cl).await;
+                    let _ = tx1.send((cl.name, ns));
+                }));
+            }
+        }
+        drop(tx);
+        let nbcl = pending.len();
+        let mut nbns = 0;
+        // The scan budget is checked outside of the runtime, so that it is
+        // respected even if a request blocks a worker
+        while !pending.is_empty() {
+            let received = match scan_timeout {
+                Some(t) => rx.recv_timeout(t.saturating_sub(start.elapsed())),
+                None => rx.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
             };
-            let lp = ListParams::default().timeout(timeout).match_any();
-            match namespaces.list(&lp).await {
-                Ok(n) => {
-                    let ns: Vec<String> = n
-                        .items
-                        .iter()
-                        .map(|name| match name.metadata.name.clone() {
-                            Some(ns) => format!("{}{}{}", ns, sep, cluster_name),
-                            None => "".to_string(),
-                        })
-                        .collect();
-                    return ns;
+            match received {
+                Ok((name, ns)) => {
+                    pending.retain(|n| *n != name);
+                    match &ns {
+                        Ok(v) => nbns += v.len(),
+                        Err(e) => warn!("{e}"),
+                    }
+                    on_result(&name, ns);
                 }
-                Err(_) => {
-                    warn!("{} is unreachable", cluster_name);
-                    return Vec::new();
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    warn!(
+                        "scan timeout reached, no answer from {}",
+                        pending.join(", ")
+                    );
+                    for task in tasks.iter() {
+                        task.abort();
+                    }
+                    for name in pending.drain(..) {
+                        on_result(&name, Err("no answer before the scan timeout".to_string()));
+                    }
                 }
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
             }
         }
-        None => return Vec::new(),
+        info!("{} namespaces found in {} clusters", nbns, nbcl);
     }
 }
 
-pub fn get_all_ns(clusters: Vec<Cluster>, sep: String) -> Vec<String> {
-    let (tx, rx) = mpsc::channel();
-    let mut nbcl = 0;
-    let mut result = Vec::new();
-    for cl in clusters {
-        if !cl.disabled {
-            nbcl += 1;
-            let tx1 = tx.clone();
-            let s = sep.clone();
-            let t = cl.timeout;
-            thread::spawn(move || {
-                let _ = match Kubeconfig::read_from(cl.kubeconfig_path) {
-                    Ok(k) => tx1.send(get_namespaces(k, s, t)),
-                    Err(e) => {
-                        warn!("{}", e);
-                        tx1.send(Vec::new())
-                    }
-                };
-            });
+impl Drop for Scanner {
+    fn drop(&mut self) {
+        // Do not wait for the requests blocked after the scan timeout
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+/// Status line of the fuzzy finder while the clusters are scanned.
+#[derive(Debug, Clone, Default)]
+pub struct ScanStatus {
+    state: Arc<Mutex<(Vec<String>, Vec<String>)>>, // pending and failed clusters
+}
+
+impl ScanStatus {
+    pub fn new(pending: Vec<String>) -> ScanStatus {
+        ScanStatus {
+            state: Arc::new(Mutex::new((pending, Vec::new()))),
         }
     }
-    thread::spawn(move || {
-        let _ = tx.send(vec!["".to_string()]);
-    });
-    for rec in rx {
-        result.extend(rec);
+
+    pub fn done(&self, cluster: &str, success: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.0.retain(|c| c != cluster);
+        if !success {
+            state.1.push(cluster.to_string());
+        }
     }
-    result.sort();
-    info!("{} namespaces found in {} clusters", result.len(), nbcl);
-    result
 }
 
-pub fn selectable_list(input: Vec<String>, query: Option<String>) -> String {
-    if input.contains(&query.clone().unwrap().to_string()) {
-        return query.unwrap().to_string();
-    };
-    let input: Vec<String> = input.into_iter().rev().collect();
-    let options = SkimOptionsBuilder::default()
-        .multi(false)
-        .query(query)
-        .select_1(false)
-        .build()
-        .unwrap();
-    let item_reader = SkimItemReader::default();
+impl SkimItem for ScanStatus {
+    fn text(&self) -> Cow<'_, str> {
+        let state = self.state.lock().unwrap();
+        let mut status = Vec::new();
+        if !state.0.is_empty() {
+            status.push(format!("pending: {}", state.0.join(", ")));
+        }
+        if !state.1.is_empty() {
+            status.push(format!("failed: {}", state.1.join(", ")));
+        }
+        if status.is_empty() {
+            status.push("all clusters scanned".to_string());
+        }
+        Cow::Owned(status.join(" | "))
+    }
+}
 
-    let items = item_reader.of_bufread(Cursor::new(input.join("\n")));
-    let selected_items = Skim::run_with(&options, Some(items))
+fn run_skim(options: &SkimOptions, items: SkimItemReceiver) -> Vec<String> {
+    let selected_items = Skim::run_with(options, Some(items))
         .map(|out| match out.final_key {
             Key::Enter => out.selected_items,
             _ => Vec::new(),
@@ -172,14 +409,103 @@ pub fn selectable_list(input: Vec<String>, query: Option<String>) -> String {
         process::exit(1);
     }
 
-    selected_items[0].output().to_string()
+    selected_items
+        .iter()
+        .map(|item| item.output().to_string())
+        .collect()
+}
+
+/// Options of the fuzzy finder: with `multi` several entries can be marked
+/// with Tab, `preview` is the command showing the details of an entry.
+#[derive(Debug, Clone, Default)]
+pub struct Picker {
+    pub multi: bool,
+    pub preview: Option<String>,
+    pub preview_window: String,
+}
+
+impl Picker {
+    fn options(&self, query: Option<String>, header_lines: usize) -> SkimOptions {
+        let mut builder = SkimOptionsBuilder::default();
+        builder
+            .multi(self.multi)
+            .query(query)
+            .select_1(false)
+            // Among the entries matching as well, the order of the list
+            // (frecency) wins
+            .tiebreak(vec![RankCriteria::Score, RankCriteria::Index])
+            .header_lines(header_lines);
+        if let Some(cmd) = &self.preview {
+            builder
+                .preview(Some(cmd.clone()))
+                .preview_window(self.preview_window.clone());
+        }
+        builder.build().unwrap()
+    }
+}
+
+/// Fuzzy finder on the given entries, the first ones are shown next to the
+/// prompt.
+pub fn selectable_list(input: Vec<String>, query: Option<String>, picker: &Picker) -> Vec<String> {
+    if input.contains(&query.clone().unwrap().to_string()) {
+        return vec![query.unwrap().to_string()];
+    };
+    let options = picker.options(query, 0);
+    let item_reader = SkimItemReader::default();
+
+    let items = item_reader.of_bufread(Cursor::new(input.join("\n")));
+    run_skim(&options, items)
+}
+
+/// Fuzzy finder fed while the clusters are scanned, the first item received
+/// is the status line.
+pub fn selectable_stream(
+    items: SkimItemReceiver,
+    query: Option<String>,
+    picker: &Picker,
+) -> Vec<String> {
+    let options = picker.options(query, 1);
+    run_skim(&options, items)
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{kube::ns_workdir, terminal::kitty::Tabcolor};
 
-    use super::Cluster;
+    use super::{Cluster, format_details, human_age};
+    use k8s_openapi::api::core::v1::{Namespace, Pod, ResourceQuota};
+
+    #[test]
+    fn test_human_age() {
+        assert_eq!(human_age(42), "42s");
+        assert_eq!(human_age(600), "10m");
+        assert_eq!(human_age(3 * 3600), "3h");
+        assert_eq!(human_age(12 * 86400), "12d");
+    }
+
+    #[test]
+    fn test_format_details() {
+        let ns: Namespace = serde_json::from_value(serde_json::json!({
+            "metadata": {"name": "my-app", "labels": {"team": "blue"}},
+            "status": {"phase": "Active"}
+        }))
+        .unwrap();
+        let quota: ResourceQuota = serde_json::from_value(serde_json::json!({
+            "metadata": {"name": "compute"},
+            "status": {"hard": {"pods": "10"}, "used": {"pods": "3"}}
+        }))
+        .unwrap();
+        let pod = |phase: &str| -> Pod {
+            serde_json::from_value(serde_json::json!({"status": {"phase": phase}})).unwrap()
+        };
+        let pods = vec![pod("Running"), pod("Running"), pod("Pending")];
+        let details = format_details("prod", &ns, &[quota], &pods);
+        assert!(details.contains("namespace   my-app\n"));
+        assert!(details.contains("phase       Active\n"));
+        assert!(details.contains("pods        3 (Pending 1, Running 2)\n"));
+        assert!(details.contains("\nlabels\n  team=blue\n"));
+        assert!(details.contains("  pods                     3/10\n"));
+    }
 
     #[test]
     fn test_ns_workdir_path_not_exist() {
@@ -196,14 +522,24 @@ mod tests {
             inactive_fg: "NONE".to_string(),
         };
         let timeout = 5;
+        let get_timeout = 3;
+        let connect_timeout = 800;
         let cluster = Cluster {
             name,
             kubeconfig_path: cluster_kubeconfig_path,
+            context: None,
+            default_user: None,
+            impersonate: None,
+            elevated_users: Vec::new(),
+            protected: false,
             workdir,
             prefixns,
             disabled,
+            maxage: 3600,
             tabcolor,
             timeout,
+            get_timeout,
+            connect_timeout,
         };
         let kubeconfig = "/tmp/path/kitty/42".to_string();
         let result = ns_workdir(&cluster, namespace, kubeconfig);
@@ -228,14 +564,24 @@ mod tests {
             inactive_fg: "NONE".to_string(),
         };
         let timeout = 5;
+        let get_timeout = 3;
+        let connect_timeout = 800;
         let cluster = Cluster {
             name,
             kubeconfig_path: cluster_kubeconfig_path,
+            context: None,
+            default_user: None,
+            impersonate: None,
+            elevated_users: Vec::new(),
+            protected: false,
             workdir,
             prefixns,
             disabled,
+            maxage: 3600,
             tabcolor,
             timeout,
+            get_timeout,
+            connect_timeout,
         };
         let kubeconfig = "/tmp/path/kitty/42".to_string();
         let result = ns_workdir(&cluster, namespace, kubeconfig);
@@ -260,14 +606,24 @@ mod tests {
             inactive_fg: "NONE".to_string(),
         };
         let timeout = 5;
+        let get_timeout = 3;
+        let connect_timeout = 800;
         let cluster = Cluster {
             name,
             kubeconfig_path: cluster_kubeconfig_path,
+            context: None,
+            default_user: None,
+            impersonate: None,
+            elevated_users: Vec::new(),
+            protected: false,
             workdir,
             prefixns,
             disabled,
+            maxage: 3600,
             tabcolor,
             timeout,
+            get_timeout,
+            connect_timeout,
         };
         let kubeconfig = "/tmp/path/kitty/42".to_string();
         let result = ns_workdir(&cluster, namespace, kubeconfig);
@@ -1,167 +1,449 @@
-use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
 use std::fs;
-use std::fs::File;
-use std::os::unix::fs::PermissionsExt;
-
-#[derive(Default, PartialEq, Debug, Serialize, Deserialize)]
+use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::os::unix::fs::{OpenOptionsExt, symlink};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Name of the extension of the kubeconfig files written by ktk
+const KTK_EXTENSION: &str = "ktk";
+
+/// Kubeconfig file of a cluster.
+///
+/// The yaml document is kept as is, so that the copy written for a tab has
+/// every field of the original (auth providers, exec plugins, tokens,
+/// extensions...), only the namespace of the context and the current
+/// context are changed.
+#[derive(Default, PartialEq, Debug)]
 pub struct Kubeconfig {
-    #[serde(rename = "apiVersion")]
-    #[serde(skip_serializing_if = "String::is_empty", default)]
-    api_version: String,
+    doc: Value,
+}
 
-    #[serde(rename = "kind")]
-    #[serde(skip_serializing_if = "String::is_empty", default)]
-    kind: String,
+impl Kubeconfig {
+    // Deserialize yaml file in struc Kubeconfig
+    pub fn new(path: String) -> Result<Kubeconfig, serde_yaml::Error> {
+        let f = std::fs::File::open(path).expect("Could not open file.");
+        let doc: Value = serde_yaml::from_reader(f)?;
+        if !doc.is_mapping() {
+            return Err(serde::de::Error::custom("a kubeconfig must be a mapping"));
+        }
+        Ok(Kubeconfig { doc })
+    }
 
-    #[serde(rename = "clusters")]
-    clusters: Vec<ClusterElement>,
+    /// Names of the contexts of the file.
+    pub fn context_names(&self) -> Vec<String> {
+        self.contexts()
+            .iter()
+            .filter_map(|c| c["name"].as_str().map(String::from))
+            .collect()
+    }
 
-    #[serde(rename = "preferences")]
-    preferences: Option<Preferences>,
+    fn contexts(&self) -> &[Value] {
+        self.doc["contexts"]
+            .as_sequence()
+            .map(|s| s.as_slice())
+            .unwrap_or_default()
+    }
 
-    #[serde(rename = "users")]
-    users: Vec<UserElement>,
+    // Context used by ktk, the current one, otherwise the first one of the file
+    fn context_index(&self) -> usize {
+        let current = self.doc["current-context"].as_str();
+        self.contexts()
+            .iter()
+            .position(|c| current.is_some() && c["name"].as_str() == current)
+            .unwrap_or(0)
+    }
 
-    #[serde(rename = "contexts")]
-    contexts: Vec<ContextElement>,
+    /// Name of the context used when none is selected.
+    pub fn current_context(&self) -> Option<String> {
+        self.context()["name"].as_str().map(String::from)
+    }
 
-    #[serde(rename = "current-context")]
-    #[serde(skip_serializing_if = "String::is_empty", default)]
-    current_context: String,
-}
+    fn context(&self) -> &Value {
+        &self.doc["contexts"][self.context_index()]
+    }
 
-#[derive(Default, PartialEq, Debug, Serialize, Deserialize)]
-pub struct ClusterElement {
-    #[serde(rename = "name")]
-    #[serde(skip_serializing_if = "String::is_empty", default)]
-    name: String,
+    /// Makes `name` the current context, returns false if the file has no
+    /// context of this name.
+    pub fn select_context(&mut self, name: &str) -> bool {
+        if !self.context_names().iter().any(|n| n == name) {
+            return false;
+        }
+        self.doc["current-context"] = name.into();
+        true
+    }
 
-    #[serde(rename = "cluster")]
-    cluster: ClusterCluster,
-}
+    // Get cluster Context in Kubeconfig
+    pub fn cluster_context(&self) -> String {
+        self.context()["context"]["cluster"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string()
+    }
 
-#[derive(Default, PartialEq, Debug, Serialize, Deserialize)]
-pub struct ClusterCluster {
-    #[serde(rename = "server")]
-    #[serde(skip_serializing_if = "String::is_empty", default)]
-    server: String,
-
-    #[serde(rename = "certificate-authority-data")]
-    #[serde(skip_serializing_if = "String::is_empty", default)]
-    certificate_authority_data: String,
-    // #[serde(rename = "insecure-skip-tls-verify")]
-    // insecure_skip_tls_verify: bool,
-}
+    // Get namespace Context in Kubeconfig
+    pub fn namespace_context(&self) -> String {
+        self.context()["context"]["namespace"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string()
+    }
 
-#[derive(Default, PartialEq, Debug, Serialize, Deserialize)]
-pub struct ContextElement {
-    #[serde(rename = "context")]
-    context: ContextContext,
+    // Change namespace in Kubeconfig
+    pub fn change_context(&mut self, namespace: String) {
+        let index = self.context_index();
+        let Some(context) = self.doc.get_mut("contexts").and_then(|c| c.get_mut(index)) else {
+            return;
+        };
+        let name = context.get("name").cloned();
+        if let Value::Mapping(context) = context {
+            let fields = context
+                .entry("context".into())
+                .or_insert(Value::Mapping(Mapping::new()));
+            if !fields.is_mapping() {
+                *fields = Value::Mapping(Mapping::new());
+            }
+            fields["namespace"] = namespace.into();
+        }
+        if let Some(name) = name {
+            self.doc["current-context"] = name;
+        }
+    }
 
-    #[serde(rename = "name")]
-    #[serde(skip_serializing_if = "String::is_empty", default)]
-    name: String,
-}
+    /// Names of the users of the file.
+    pub fn user_names(&self) -> Vec<String> {
+        self.doc["users"]
+            .as_sequence()
+            .map(|s| s.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|u| u["name"].as_str().map(String::from))
+            .collect()
+    }
 
-#[derive(Default, PartialEq, Debug, Serialize, Deserialize)]
-pub struct ContextContext {
-    #[serde(rename = "cluster")]
-    cluster: String,
+    // Get user Context in Kubeconfig
+    pub fn user_context(&self) -> String {
+        self.context()["context"]["user"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string()
+    }
 
-    #[serde(rename = "user")]
-    user: String,
+    /// Makes the context use the credentials of `user`, returns false if the
+    /// file has no user of this name.
+    pub fn change_user(&mut self, user: &str) -> bool {
+        if !self.user_names().iter().any(|n| n == user) {
+            return false;
+        }
+        let index = self.context_index();
+        match self.doc["contexts"].get_mut(index) {
+            Some(context) if context["context"].is_mapping() => {
+                context["context"]["user"] = user.into();
+                true
+            }
+            _ => false,
+        }
+    }
 
-    #[serde(rename = "namespace")]
-    #[serde(skip_serializing_if = "String::is_empty", default)]
-    namespace: String,
-}
+    /// Makes the user of the context act as `name` (like `kubectl --as`),
+    /// returns false if the user of the context is not in the file.
+    pub fn impersonate(&mut self, name: &str) -> bool {
+        let user = self.user_context();
+        let Some(Value::Sequence(users)) = self.doc.get_mut("users") else {
+            return false;
+        };
+        match users.iter_mut().find(|u| u["name"].as_str() == Some(&user)) {
+            Some(entry) if entry["user"].is_mapping() => {
+                entry["user"]["as"] = name.into();
+                true
+            }
+            _ => false,
+        }
+    }
 
-#[derive(Default, PartialEq, Debug, Serialize, Deserialize)]
-pub struct Preferences {}
+    /// Name of the ktk cluster recorded in the file by `set_ktk_cluster`.
+    pub fn ktk_cluster(&self) -> Option<String> {
+        self.doc["extensions"]
+            .as_sequence()?
+            .iter()
+            .find(|e| e["name"].as_str() == Some(KTK_EXTENSION))
+            .and_then(|e| e["extension"]["cluster"].as_str())
+            .map(String::from)
+    }
 
-#[derive(Default, PartialEq, Debug, Serialize, Deserialize)]
-pub struct UserElement {
-    #[serde(rename = "name")]
-    #[serde(skip_serializing_if = "String::is_empty", default)]
-    name: String,
+    /// Records the ktk cluster in an extension of the file, several ktk
+    /// clusters may use the same kubeconfig cluster with other contexts.
+    pub fn set_ktk_cluster(&mut self, cluster: &str) {
+        let mut extension = Mapping::new();
+        extension.insert("name".into(), KTK_EXTENSION.into());
+        extension.insert("extension".into(), Value::Mapping(Mapping::new()));
+        extension["extension"]["cluster"] = cluster.into();
+        if !self.doc["extensions"].is_sequence() {
+            self.doc["extensions"] = Value::Sequence(Vec::new());
+        }
+        if let Value::Sequence(extensions) = &mut self.doc["extensions"] {
+            extensions.retain(|e| e["name"].as_str() != Some(KTK_EXTENSION));
+            extensions.push(Value::Mapping(extension));
+        }
+    }
 
-    #[serde(rename = "user")]
-    user: UserUser,
+    // Write Kubeconfig struct in yaml file, a temporary file is renamed over
+    // it so that a link is replaced instead of followed and a shell never
+    // reads a partially written file
+    pub fn write(&self, path: String, filename: String) {
+        fs::create_dir_all(path.clone()).expect("Could not create destination dir");
+        let kubefile = format!("{path}/{filename}");
+        let tmp = format!("{kubefile}.{}.tmp", std::process::id());
+        let f = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp)
+            .expect("File should exist");
+        serde_yaml::to_writer(f, &self.doc).unwrap();
+        fs::rename(tmp, kubefile).unwrap();
+    }
 }
 
-#[derive(Default, PartialEq, Debug, Serialize, Deserialize)]
-pub struct UserUser {
-    #[serde(rename = "client-certificate-data")]
-    #[serde(skip_serializing_if = "String::is_empty", default)]
-    client_certificate_data: String,
+/// Creates the empty file `path/<prefix>-<n>` with the first free `n` and
+/// returns its name, each tab or pane has its own kubeconfig file.
+pub fn reserve(path: &str, prefix: &str) -> std::io::Result<String> {
+    fs::create_dir_all(path)?;
+    let mut n = 1;
+    loop {
+        let filename = format!("{prefix}-{n}");
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(format!("{path}/{filename}"))
+        {
+            Ok(_) => return Ok(filename),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => n += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
 
-    #[serde(rename = "client-key-data")]
-    #[serde(skip_serializing_if = "String::is_empty", default)]
-    client_key_data: String,
+/// Link `path/filename` to the kubeconfig file `path/target`, the link is
+/// replaced atomically.
+pub fn link(path: &str, target: &str, filename: &str) -> std::io::Result<()> {
+    let link = format!("{path}/{filename}");
+    let tmp = format!("{link}.{}.tmp", std::process::id());
+    let _ = fs::remove_file(&tmp);
+    symlink(target, &tmp)?;
+    fs::rename(tmp, link)
+}
 
-    #[serde(rename = "exec")]
-    exec: Option<Exec>,
+// The entry was changed less than `grace` ago, it may be used by a ktk
+// which has not linked it yet or by a tab missing from its list
+fn is_recent(path: &Path, grace: Duration) -> bool {
+    fs::symlink_metadata(path)
+        .and_then(|m| m.modified())
+        .map(|t| t.elapsed().unwrap_or_default() < grace)
+        .unwrap_or(true)
 }
 
-#[derive(Default, PartialEq, Debug, Serialize, Deserialize)]
-pub struct Exec {
-    #[serde(rename = "apiVersion")]
-    api_version: String,
+/// Removes the links of `path` whose tab or pane is no longer open
+/// according to `is_open`, then the `ctx-*` files of `path` which no link
+/// of the directories of `root` points to. The entries changed less than
+/// `grace` ago are kept.
+pub fn prune(
+    root: &str,
+    path: &str,
+    grace: Duration,
+    is_open: impl Fn(&str) -> bool,
+) -> std::io::Result<()> {
+    for entry in fs::read_dir(path)?.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.file_type().is_ok_and(|t| t.is_symlink())
+            && !name.ends_with(".tmp")
+            && !is_open(&name)
+            && !is_recent(&entry.path(), grace)
+        {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    // The link of a new OS window is in the directory of this window
+    let mut targets: Vec<PathBuf> = Vec::new();
+    for dir in fs::read_dir(root)?.flatten() {
+        if !dir.file_type().is_ok_and(|t| t.is_dir()) {
+            continue;
+        }
+        for entry in fs::read_dir(dir.path())?.flatten() {
+            if let Ok(target) = fs::read_link(entry.path()) {
+                targets.push(target);
+            }
+        }
+    }
+    for entry in fs::read_dir(path)?.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.file_type().is_ok_and(|t| t.is_file())
+            && name.starts_with("ctx-")
+            && !name.ends_with(".tmp")
+            && !targets.contains(&Path::new(path).join(&name))
+            && !is_recent(&entry.path(), grace)
+        {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
 
-    #[serde(rename = "args")]
-    args: Vec<String>,
+#[cfg(test)]
+mod tests {
+    use super::{Kubeconfig, link, prune, reserve};
+    use serde_yaml::Value;
+    use std::fs;
+    use std::time::Duration;
+
+    // The kubeconfig written for a tab is the fixture with only the
+    // namespace of the context and the current context changed
+    fn check_roundtrip(file: &str, cluster: &str) {
+        let source = format!("./tests/{file}");
+        let mut kcf = Kubeconfig::new(source.clone()).unwrap();
+        assert_eq!(kcf.cluster_context(), cluster);
+        kcf.change_context("my-ns".to_string());
+        assert_eq!(kcf.namespace_context(), "my-ns");
+
+        let dir = std::env::temp_dir().join(format!("ktk-test-kubeconfig-{}", std::process::id()));
+        kcf.write(dir.display().to_string(), file.to_string());
+        let written: Value =
+            serde_yaml::from_str(&fs::read_to_string(dir.join(file)).unwrap()).unwrap();
+        let _ = fs::remove_file(dir.join(file));
+
+        let mut expected: Value =
+            serde_yaml::from_str(&fs::read_to_string(source).unwrap()).unwrap();
+        expected["contexts"][0]["context"]["namespace"] = "my-ns".into();
+        expected["current-context"] = expected["contexts"][0]["name"].clone();
+        assert_eq!(written, expected);
+    }
 
-    #[serde(rename = "command")]
-    command: String,
+    #[test]
+    fn test_roundtrip_eks() {
+        check_roundtrip(
+            "kubeconfig-eks.yaml",
+            "arn:aws:eks:eu-west-1:123456789012:cluster/prod",
+        );
+    }
 
-    #[serde(rename = "env")]
-    env: Vec<Option<serde_json::Value>>,
+    #[test]
+    fn test_roundtrip_gke() {
+        check_roundtrip("kubeconfig-gke.yaml", "gke_my-project_europe-west1_staging");
+    }
 
-    #[serde(rename = "installHint")]
-    install_hint: String,
+    #[test]
+    fn test_roundtrip_oidc() {
+        check_roundtrip("kubeconfig-oidc.yaml", "corp");
+    }
 
-    #[serde(rename = "provideClusterInfo")]
-    provide_cluster_info: bool,
-}
+    #[test]
+    fn test_roundtrip_token() {
+        check_roundtrip("kubeconfig-token.yaml", "lab");
+    }
 
-impl Kubeconfig {
-    // Deserialize yaml file in struc Kubeconfig
-    pub fn new(path: String) -> Result<Kubeconfig, serde_yaml::Error> {
-        let f = std::fs::File::open(path).expect("Could not open file.");
-        serde_yaml::from_reader(f)
+    #[test]
+    fn test_select_context() {
+        let mut kcf = Kubeconfig::new("./tests/kubeconfig-token.yaml".to_string()).unwrap();
+        assert_eq!(kcf.context_names(), vec!["lab", "lab-basic"]);
+        assert_eq!(kcf.namespace_context(), "kube-system");
+        assert!(!kcf.select_context("nope"));
+        assert!(kcf.select_context("lab-basic"));
+        kcf.change_context("web".to_string());
+        assert_eq!(kcf.cluster_context(), "lab");
+        assert_eq!(kcf.namespace_context(), "web");
+        assert_eq!(kcf.doc["current-context"].as_str(), Some("lab-basic"));
+        assert_eq!(
+            kcf.doc["contexts"][0]["context"]["namespace"].as_str(),
+            Some("kube-system")
+        );
+
+        assert_eq!(kcf.ktk_cluster(), None);
+        kcf.set_ktk_cluster("lab-basic");
+        kcf.set_ktk_cluster("lab-ro");
+        assert_eq!(kcf.ktk_cluster(), Some("lab-ro".to_string()));
+        assert_eq!(kcf.doc["extensions"].as_sequence().unwrap().len(), 2);
     }
 
-    // Get namespace Context in Kubeconfig
-    pub fn cluster_context(&self) -> String {
-        if !self.contexts.is_empty() {
-            return self.contexts[0].context.cluster.clone();
-        };
-        "".to_string()
+    #[test]
+    fn test_change_user() {
+        let mut kcf = Kubeconfig::new("./tests/kubeconfig-token.yaml".to_string()).unwrap();
+        assert_eq!(kcf.user_names(), vec!["admin", "ci", "basic"]);
+        assert!(kcf.select_context("lab-basic"));
+        assert_eq!(kcf.user_context(), "basic");
+        assert!(!kcf.change_user("root"));
+        assert!(kcf.change_user("ci"));
+        assert_eq!(kcf.user_context(), "ci");
+        assert_eq!(
+            kcf.doc["contexts"][0]["context"]["user"].as_str(),
+            Some("admin")
+        );
     }
 
-    // Get namespace Context in Kubeconfig
-    pub fn namespace_context(&self) -> String {
-        if !self.contexts.is_empty() {
-            return self.contexts[0].context.namespace.clone();
-        };
-        "".to_string()
+    #[test]
+    fn test_impersonate() {
+        let mut kcf = Kubeconfig::new("./tests/kubeconfig-token.yaml".to_string()).unwrap();
+        assert!(kcf.impersonate("viewer"));
+        assert_eq!(kcf.doc["users"][0]["user"]["as"].as_str(), Some("viewer"));
+        assert!(kcf.doc["users"][0]["user"]["token"].is_string());
+        assert!(kcf.doc["users"][1]["user"]["as"].is_null());
+        let mut kcf = Kubeconfig::new("./tests/kubeconfig-oidc.yaml".to_string()).unwrap();
+        assert!(kcf.impersonate("viewer"));
     }
 
-    // Change namespace in Kubeconfig
-    pub fn change_context(&mut self, namespace: String) {
-        if !self.contexts.is_empty() {
-            self.contexts[0].context.namespace = namespace;
-            self.current_context = self.contexts[0].name.clone();
-        };
-        // ToDo change user
+    #[test]
+    fn test_write_replaces_link() {
+        let dir = std::env::temp_dir().join(format!("ktk-test-link-{}", std::process::id()));
+        let dir = dir.display().to_string();
+        let _ = fs::remove_dir_all(&dir);
+        let first = reserve(&dir, "ctx-lab").unwrap();
+        let second = reserve(&dir, "ctx-lab").unwrap();
+        assert_ne!(first, second);
+        assert_eq!(fs::read_to_string(format!("{dir}/{first}")).unwrap(), "");
+
+        // Another tab of the same context keeps its file
+        link(&dir, &format!("{dir}/{first}"), "tab-1").unwrap();
+        link(&dir, &format!("{dir}/{first}"), "tab-1").unwrap();
+        let kcf = Kubeconfig::new("./tests/kubeconfig-token.yaml".to_string()).unwrap();
+        kcf.write(dir.clone(), "tab-1".to_string());
+        assert!(
+            !fs::symlink_metadata(format!("{dir}/tab-1"))
+                .unwrap()
+                .is_symlink()
+        );
+        assert_eq!(fs::read_to_string(format!("{dir}/{first}")).unwrap(), "");
+        fs::remove_dir_all(&dir).unwrap();
     }
 
-    // Write Kubeconfig struct in yaml file
-    pub fn write(&self, path: String, filename: String) {
-        fs::create_dir_all(path.clone()).expect("Could not create destination dir");
-        let kubefile = format!("{path}/{filename}");
-        let f = File::create(kubefile.clone()).expect("File should exist");
-        serde_yaml::to_writer(f, &self).unwrap();
-        fs::set_permissions(kubefile, fs::Permissions::from_mode(0o600)).unwrap();
+    #[test]
+    fn test_prune() {
+        let root = std::env::temp_dir().join(format!("ktk-test-prune-{}", std::process::id()));
+        let root = root.display().to_string();
+        let dir = format!("{root}/kitty-1");
+        let other = format!("{root}/kitty-2");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&other).unwrap();
+        let open = reserve(&dir, "ctx-lab").unwrap();
+        let closed = reserve(&dir, "ctx-lab").unwrap();
+        let window = reserve(&dir, "ctx-lab").unwrap();
+        reserve(&dir, "ctx-lab").unwrap();
+        link(&dir, &format!("{dir}/{open}"), "1").unwrap();
+        link(&dir, &format!("{dir}/{closed}"), "2").unwrap();
+        link(&other, &format!("{dir}/{window}"), "pane-3").unwrap();
+
+        // Everything is kept during the grace period
+        prune(&root, &dir, Duration::from_secs(60), |_| false).unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 6);
+
+        prune(&root, &dir, Duration::ZERO, |name| name == "1").unwrap();
+        let mut names: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["1".to_string(), open, window]);
+        fs::remove_dir_all(&root).unwrap();
     }
 }
@@ -4,22 +4,36 @@
 //! When `ktk` open a new tab, you go directly to a working directory specific to the cluster and the namespace.
 //!
 //! `ktk` can easily manage dozens of clusters with thousands of namespaces.
+mod cache;
 mod config;
+mod discovery;
+mod favorites;
+mod history;
 mod kube;
 mod kubeconfig;
 mod ohmyposh;
+mod snapshot;
 mod terminal;
 
 use clap::{
-    Arg, ArgAction, ValueHint, command, crate_authors, crate_name, crate_version, value_parser,
+    Arg, ArgAction, Command, ValueHint, command, crate_authors, crate_name, crate_version,
+    value_parser,
 };
 use clap_complete::aot::{Generator, Shell, generate};
 use regex::bytes::Regex;
-use std::fs::OpenOptions;
+use serde::Serialize;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, Write};
+use std::os::unix::process::CommandExt;
 use std::path::{Path, PathBuf};
-use std::{env, io, process};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use std::{env, io, process, thread};
 
-use log::{debug, error, info};
+use skim::prelude::{Arc, SkimItemReceiver, SkimItemSender, unbounded};
+
+use log::{debug, error, info, warn};
 use simplelog::*;
 
 fn config_file() -> String {
@@ -49,6 +63,9 @@ fn clap_command(pns: Vec<String>, pnsinc: Vec<String>) -> clap::Command {
         r#"<bold><green>Examples:</green></bold>
   <dim>$</dim> <bold>ktk kube-system::production</bold>
   <dim>$</dim> <bold>ktk -t -C kube-system</bold>
+  <dim>$</dim> <bold>ktk -m my-app</bold>
+  <dim>$</dim> <bold>ktk --layout vsplit kube-system::staging</bold>
+  <dim>$</dim> <bold>ktk -- tabs::production</bold>
 "#
     );
     let override_usage: &'static str = color_print::cstr!(
@@ -72,7 +89,8 @@ fn clap_command(pns: Vec<String>, pnsinc: Vec<String>) -> clap::Command {
         .arg(
             Arg::new("namespace")
                 .help("Namespace to operate on")
-                .required_unless_present_any(["force","evaldir","cluster","completion","list-clusters-colors","list-clusters-names","oh-my-posh-json"])
+                .long_help("Namespace to operate on.\nA namespace named like a subcommand (config, fav, session, tabs, snapshot...) is given after --, like ktk -- tabs::production")
+                .required_unless_present_any(["force","evaldir","cluster","completion","list-clusters-colors","list-clusters-names","oh-my-posh-json","background-refresh","recent"])
                 .value_hint(ValueHint::Other)
         )
         .arg(
@@ -96,6 +114,7 @@ fn clap_command(pns: Vec<String>, pnsinc: Vec<String>) -> clap::Command {
                 .help("Sets a custom config file")
                 .long_help("Sets a custom config file.\nIt is possible to set the environment variable KTKONFIG to redefine the default config file.")
                 .default_value(config_file())
+                .global(true)
                 .value_hint(ValueHint::FilePath)
                 .value_parser(value_parser!(PathBuf)),
         )
@@ -105,7 +124,7 @@ fn clap_command(pns: Vec<String>, pnsinc: Vec<String>) -> clap::Command {
                 .long("force")
                 .action(clap::ArgAction::SetTrue)
                 .help("Force reconstruct cache of namespace")
-                .long_help("This option will rebuild the whole cache by requesting all clusters. Each line will contain the namespace name, followed by the cluster name. If a cluster is not available the cache data for it will be deleted."),
+                .long_help("This option will rebuild the whole cache by requesting all clusters. If a cluster is not available, the namespaces of its last successful scan are kept."),
         )
         .arg(
             Arg::new("noscan")
@@ -113,9 +132,43 @@ fn clap_command(pns: Vec<String>, pnsinc: Vec<String>) -> clap::Command {
                 .long("noscan")
                 .action(clap::ArgAction::SetTrue)
                 .help("Do not reconstruct cache of namespace")
-                .long_help("The cache is automatically rebuilt every \"maxage\" seconds. This option allows you to ignore this value to avoid refreshing the cache.")
+                .long_help("The cache of each cluster is automatically rebuilt every \"maxage\" seconds. This option allows you to ignore this value to avoid refreshing the cache.")
                 .conflicts_with_all(["force"]),
         )
+        .arg(
+            Arg::new("background-refresh")
+                .long("background-refresh")
+                .action(clap::ArgAction::SetTrue)
+                .hide(true)
+                .help("Refresh the outdated clusters of the cache and exit")
+                .conflicts_with_all(["namespace", "force", "noscan"]),
+        )
+        .arg(
+            Arg::new("multi")
+                .short('m')
+                .long("multi")
+                .action(clap::ArgAction::SetTrue)
+                .help("Select several namespaces with Tab and open one tab for each")
+                .conflicts_with_all(["tab", "evaldir", "completion"]),
+        )
+        .arg(
+            Arg::new("recent")
+                .short('r')
+                .long("recent")
+                .action(clap::ArgAction::SetTrue)
+                .help("Search only in the recently used namespaces")
+                .long_help("Search only in the namespaces selected during the last 90 days, the most frequently and recently used first.")
+                .conflicts_with_all(["force", "evaldir", "completion"]),
+        )
+        .arg(
+            Arg::new("preview")
+                .short('p')
+                .long("preview")
+                .action(clap::ArgAction::SetTrue)
+                .help("Show the details of the highlighted namespace in the search")
+                .long_help("Show the labels, annotations, phase, age, quotas and pods of the highlighted namespace in a preview pane of the search.\nThe preview can be enabled permanently with preview.enabled in the config file.")
+                .conflicts_with_all(["evaldir", "completion"]),
+        )
         .arg(
             Arg::new("cluster")
                 .short('C')
@@ -171,12 +224,32 @@ fn clap_command(pns: Vec<String>, pnsinc: Vec<String>) -> clap::Command {
                 .help("Change namespace without change tab (like kubens)")
                 .conflicts_with_all(["evaldir"]),
         )
+        .arg(
+            Arg::new("layout")
+                .long("layout")
+                .action(ArgAction::Set)
+                .value_parser(terminal::Layout::NAMES)
+                .default_value("tab")
+                .help("Open the namespace in a tab, a split or an OS window")
+                .long_help("Open the namespace in a new tab, in a pane beside (vsplit) or below (hsplit) the current one, or in a new OS window.\nThe splits are always created, even if a tab of the namespace already exists.")
+                .conflicts_with_all(["tab", "evaldir", "completion"]),
+        )
+        .arg(
+            Arg::new("user")
+                .short('u')
+                .long("user")
+                .action(ArgAction::Set)
+                .help("Open the namespace with another user of the kubeconfig")
+                .long_help("Open the namespace with another user of the kubeconfig, instead of the default one of the cluster.\nThe user can also be given after the cluster: namespace::cluster::user")
+                .conflicts_with_all(["evaldir", "completion"]),
+        )
         .arg(
             Arg::new("debug")
                 .short('d')
                 .long("debug")
                 .action(clap::ArgAction::SetTrue)
                 .help("Record debug event in log file")
+                .global(true)
         )
         .arg(
             Arg::new("evaldir")
@@ -196,6 +269,107 @@ fn clap_command(pns: Vec<String>, pnsinc: Vec<String>) -> clap::Command {
                .conflicts_with_all(["namespace"])
                .value_parser(clap::value_parser!(Shell)),
         )
+        .subcommand(
+            Command::new("config")
+                .about("Manage the configuration file")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("validate")
+                        .about("Check the configuration file")
+                        .long_about("Check the configuration file.\nUnknown keys, wrong types and likely mistakes are reported with their location and the command exits with a non-zero code.")
+                )
+        )
+        .subcommand(
+            Command::new("fav")
+                .about("Manage the favorite namespaces")
+                .long_about("Manage the favorite namespaces of the configuration file.\nThe favorites are shown first in the search and can be opened directly with their alias.")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("add")
+                        .about("Add a favorite namespace")
+                        .arg(
+                            Arg::new("entry")
+                                .help("namespace::cluster")
+                                .required(true)
+                        )
+                        .arg(
+                            Arg::new("alias")
+                                .short('a')
+                                .long("alias")
+                                .action(ArgAction::Set)
+                                .help("Short name to open the namespace directly")
+                        )
+                )
+                .subcommand(
+                    Command::new("remove")
+                        .about("Remove a favorite namespace")
+                        .arg(
+                            Arg::new("entry")
+                                .help("namespace::cluster or alias")
+                                .required(true)
+                        )
+                )
+                .subcommand(Command::new("list").about("List the favorite namespaces"))
+        )
+        .subcommand(
+            Command::new("session")
+                .about("Open the tabs of a session")
+                .long_about("Open the tabs of a session of the configuration file, the existing tabs are focused.\nThe tabs are opened in a tmux session or a wezterm workspace of the same name.")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("open")
+                        .about("Open or focus the tabs of a session")
+                        .arg(
+                            Arg::new("name")
+                                .help("Name of the session")
+                                .required(true)
+                        )
+                )
+                .subcommand(Command::new("list").about("List the sessions"))
+        )
+        .subcommand(
+            Command::new("tabs")
+                .about("List the ktk tabs of the terminal")
+                .long_about("List the ktk tabs of all the windows of the terminal with their window, title, namespace, cluster, user, kubeconfig file and color.\nWith --pick, the tab to focus is chosen with the fuzzy finder.")
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .action(ArgAction::SetTrue)
+                        .help("Output the tabs in JSON")
+                )
+                .arg(
+                    Arg::new("pick")
+                        .long("pick")
+                        .action(ArgAction::SetTrue)
+                        .help("Choose a tab to focus with the fuzzy finder")
+                        .conflicts_with("json")
+                )
+        )
+        .subcommand(
+            Command::new("snapshot")
+                .about("Save and restore the ktk tabs")
+                .long_about("Save the ktk tabs of the terminal and open them again, with new kubeconfig files, after a crash of the terminal or a reboot.")
+                .subcommand_required(true)
+                .subcommand(Command::new("save").about("Save the namespaces of the ktk tabs"))
+                .subcommand(Command::new("restore").about("Open the saved tabs"))
+        )
+        .subcommand(
+            Command::new("preview")
+                .about("Show the details of a namespace, used by the preview of the search")
+                .hide(true)
+                .arg(
+                    Arg::new("entry")
+                        .help("namespace::cluster")
+                        .required(true)
+                )
+        )
+        .subcommand(
+            Command::new("wezterm-lua")
+                .about("Print the WezTerm Lua handler coloring the tabs")
+                .long_about("Print the WezTerm Lua handler coloring the tabs.\nThe output is a module to save in the WezTerm configuration directory and to load with require.")
+        )
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
         .version(crate_version!())
         .long_version(
             format!("{}\n{:<12} :  {}\n{:<12} :  {}\n{:<12} :  {}",
@@ -217,6 +391,41 @@ fn print_completions<G: Generator>(g: G, cmd: &mut clap::Command) {
     generate(g, cmd, cmd.get_name().to_string(), &mut io::stdout());
 }
 
+// The terminal logger is muted while the fuzzy search is displayed
+static TERM_LOG_MUTED: AtomicBool = AtomicBool::new(false);
+
+struct MutableTermLogger(Box<TermLogger>);
+
+impl log::Log for MutableTermLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        !TERM_LOG_MUTED.load(Ordering::Relaxed) && self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !TERM_LOG_MUTED.load(Ordering::Relaxed) {
+            self.0.log(record)
+        }
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
+
+impl SharedLogger for MutableTermLogger {
+    fn level(&self) -> LevelFilter {
+        self.0.level()
+    }
+
+    fn config(&self) -> Option<&simplelog::Config> {
+        self.0.config()
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn log::Log> {
+        Box::new(*self)
+    }
+}
+
 fn configlog(activedebug: bool) {
     // Logger
     let conflog = ConfigBuilder::new()
@@ -232,12 +441,12 @@ fn configlog(activedebug: bool) {
         log_level_file = LevelFilter::Debug;
     }
     CombinedLogger::init(vec![
-        TermLogger::new(
+        Box::new(MutableTermLogger(TermLogger::new(
             log_level_term,
             conflog.clone(),
             TerminalMode::Mixed,
             ColorChoice::Auto,
-        ),
+        ))),
         WriteLogger::new(
             log_level_file,
             conflog,
@@ -260,14 +469,23 @@ fn evaldir(conf: &config::Context) {
     //   eval "$(echo $kubedir)"
     // fi
     let term = terminal::detect();
-    let idpath = term.id_path_of_focus_tab();
-    debug!("idpath : {:?}", idpath);
-    if idpath.is_some() {
-        let kubeconfig = format!("{}/{}", conf.kubetmp, idpath.unwrap());
-        if !Path::new(&kubeconfig).exists() {
-            debug!("file not found : {:?}", kubeconfig);
-            process::exit(1)
-        }
+    // The kubeconfig file of the pane, otherwise the one of its tab,
+    // otherwise the one given to the shell when ktk launched it
+    let pane = term
+        .id_of_focus_pane()
+        .map(|id| format!("{}/{}/pane-{id}", conf.kubetmp, term.identifier()));
+    let tab = term
+        .id_path_of_focus_tab()
+        .map(|idpath| format!("{}/{}", conf.kubetmp, idpath));
+    let launched = env::var("KUBECONFIG")
+        .ok()
+        .filter(|v| v.starts_with(&format!("{}/", conf.kubetmp)));
+    debug!("kubeconfig of pane: {pane:?}, tab: {tab:?}, launch: {launched:?}");
+    let found = [pane, tab, launched]
+        .into_iter()
+        .flatten()
+        .find(|v| Path::new(v).exists());
+    if let Some(kubeconfig) = found {
         let kcf = match kubeconfig::Kubeconfig::new(kubeconfig.clone()) {
             Ok(v) => v,
             Err(e) => {
@@ -275,7 +493,9 @@ fn evaldir(conf: &config::Context) {
                 process::exit(6)
             }
         };
-        let cluster_context = kcf.cluster_context();
+        // The kubeconfig files written by ktk record their cluster, the
+        // other ones are found by the name of the cluster of their context
+        let cluster_context = kcf.ktk_cluster().unwrap_or_else(|| kcf.cluster_context());
         let namespace_context = kcf.namespace_context();
         let cluster = match conf.cluster_named(&cluster_context) {
             Some(v) => v,
@@ -291,30 +511,792 @@ fn evaldir(conf: &config::Context) {
             "{}",
             kube::ns_workdir(cluster, namespace_context, kubeconfig)
         );
+    } else {
+        debug!("kubeconfig file not found");
+        process::exit(1)
+    }
+}
+
+fn refresh_in_background(config_path: &Path, matches: &clap::ArgMatches) {
+    // Refresh the outdated clusters in a detached ktk,
+    // the current one uses the cache as it is.
+    let exe = match env::current_exe() {
+        Ok(v) => v,
+        Err(e) => {
+            warn!("Unable to refresh the cache in background: {e}");
+            return;
+        }
+    };
+    let mut cmd = process::Command::new(exe);
+    cmd.arg("--config")
+        .arg(config_path)
+        .arg("--background-refresh");
+    if matches.get_flag("debug") {
+        cmd.arg("--debug");
+    }
+    if matches.get_flag("wait") {
+        cmd.arg("--wait");
+    }
+    match cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()
+    {
+        Ok(child) => debug!("refresh the cache in background, pid {}", child.id()),
+        Err(e) => warn!("Unable to refresh the cache in background: {e}"),
+    }
+}
+
+fn validate_config(config_path: &Path) -> i32 {
+    // Report every problem of the configuration file,
+    // the exit code is not null if there is at least one.
+    match config::ConfigFile::load(config_path) {
+        Ok(cfg) => {
+            let mut warnings = cfg.warnings();
+            let count = cfg.clusters.len();
+            // The clusters of the favorites and sessions may be discovered
+            warnings.extend(config::Context::from_config(cfg, config_path, false).warnings());
+            for w in warnings.iter() {
+                eprintln!("{}: {w}", config_path.display());
+            }
+            if !warnings.is_empty() {
+                return 1;
+            }
+            println!(
+                "{}: configuration is valid ({count} clusters)",
+                config_path.display(),
+            );
+            0
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            1
+        }
+    }
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+// Command run by the fuzzy search to preview the highlighted entry,
+// in kubens mode the entries have no cluster and `suffix` adds it.
+fn preview_command(config_path: &Path, suffix: &str) -> String {
+    let exe = env::current_exe().unwrap_or_else(|_| PathBuf::from(crate_name!()));
+    let mut cmd = format!(
+        "{} preview --config {} {{}}",
+        shell_quote(&exe.display().to_string()),
+        shell_quote(&config_path.display().to_string())
+    );
+    if !suffix.is_empty() {
+        cmd.push_str(&shell_quote(suffix));
+    }
+    cmd
+}
+
+fn preview(conf: &config::Context, entry: &str) -> i32 {
+    // Print the details of the namespace for the preview pane,
+    // the errors are printed too since they are shown in the pane.
+    let Some((namespace, cluster)) = entry.split_once(&conf.separator) else {
+        println!("{entry}: no cluster");
+        return 1;
+    };
+    match conf.namespace_details(namespace, cluster) {
+        Ok(details) => {
+            print!("{details}");
+            0
+        }
+        Err(e) => {
+            println!("{e}");
+            1
+        }
+    }
+}
+
+fn favorites_command(conf: &config::Context, sub: &clap::ArgMatches) -> i32 {
+    // Edit the favorites of the config file, the errors are printed on stderr
+    let config_path = &conf.config_filename;
+    let source = match std::fs::read_to_string(config_path) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{}: {e}", config_path.display());
+            return 1;
+        }
+    };
+    let sep = &conf.separator;
+    let result = match sub.subcommand() {
+        Some(("add", args)) => {
+            let entry = args.get_one::<String>("entry").unwrap();
+            let alias = args.get_one::<String>("alias").cloned();
+            let Some((namespace, cluster)) = entry.split_once(sep.as_str()) else {
+                eprintln!("{entry}: expected namespace{sep}cluster");
+                return 1;
+            };
+            if conf.cluster_named(cluster).is_none() {
+                eprintln!("{entry}: cluster {cluster} not found in config file");
+                return 1;
+            }
+            if conf
+                .favorites
+                .iter()
+                .any(|f| f.namespace == namespace && f.cluster == cluster)
+            {
+                eprintln!("{entry}: already a favorite");
+                return 1;
+            }
+            if let Some(a) = &alias
+                && let Some(other) = conf.favorite_by_alias(a)
+            {
+                eprintln!("{a}: alias already used by {other}");
+                return 1;
+            }
+            let fav = config::Favorite {
+                namespace: namespace.to_string(),
+                cluster: cluster.to_string(),
+                alias,
+            };
+            favorites::add(&source, &conf.favorites, &fav)
+        }
+        Some(("remove", args)) => {
+            let entry = args.get_one::<String>("entry").unwrap();
+            let index = conf.favorites.iter().position(|f| {
+                f.alias.as_ref() == Some(entry)
+                    || format!("{}{sep}{}", f.namespace, f.cluster) == *entry
+            });
+            match index {
+                Some(i) => favorites::remove(&source, &conf.favorites, i),
+                None => {
+                    eprintln!("{entry}: not a favorite");
+                    return 1;
+                }
+            }
+        }
+        _ => {
+            let entries: Vec<String> = conf
+                .favorites
+                .iter()
+                .map(|f| format!("{}{sep}{}", f.namespace, f.cluster))
+                .collect();
+            let width = entries.iter().map(|e| e.len()).max().unwrap_or(0);
+            for (entry, f) in entries.iter().zip(conf.favorites.iter()) {
+                match &f.alias {
+                    Some(alias) => println!("{entry:<width$}  {alias}"),
+                    None => println!("{entry}"),
+                }
+            }
+            return 0;
+        }
+    };
+    match result.and_then(|s| std::fs::write(config_path, s).map_err(|e| e.to_string())) {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("{}: {e}", config_path.display());
+            1
+        }
+    }
+}
+
+fn session_command(conf: &config::Context, sub: &clap::ArgMatches) -> i32 {
+    // Open the tabs of a session of the config file, the errors are printed on stderr
+    let sep = &conf.separator;
+    let Some(("open", args)) = sub.subcommand() else {
+        for session in &conf.sessions {
+            let entries: Vec<String> = session
+                .tabs
+                .iter()
+                .map(|t| format!("{}{sep}{}", t.namespace, t.cluster))
+                .collect();
+            println!("{}: {}", session.name, entries.join(" "));
+        }
+        return 0;
+    };
+    let name = args.get_one::<String>("name").unwrap();
+    let Some(session) = conf.session_named(name) else {
+        eprintln!("{name}: session not found in config file");
+        return 1;
+    };
+    if let Some(tab) = session
+        .tabs
+        .iter()
+        .find(|t| conf.cluster_named(&t.cluster).is_none())
+    {
+        eprintln!("{name}: cluster {} not found in config file", tab.cluster);
+        return 1;
+    }
+    let mut term = terminal::detect();
+    if !term.enter_session(name) {
+        info!("the terminal has no sessions, the tabs of {name} are opened in the current window");
+    }
+    for tab in &session.tabs {
+        let mut choice = format!("{}{sep}{}", tab.namespace, tab.cluster);
+        if let Some(user) = &tab.user {
+            choice = format!("{choice}{sep}{user}");
+        }
+        let created = open_tab(
+            conf,
+            term.as_mut(),
+            &choice,
+            None,
+            false,
+            terminal::Layout::Tab,
+        );
+        if created && let Some(command) = &tab.command {
+            term.send_command(command);
+        }
+    }
+    0
+}
+
+/// ktk tab of the terminal, listed by `ktk tabs`
+#[derive(Debug, Serialize)]
+struct OpenTab {
+    window: String, // directory of the window or of the session in kubetmp
+    id: String,
+    title: String,
+    session: Option<String>,
+    namespace: String,
+    cluster: String,
+    user: Option<String>,
+    kubeconfig: String,
+    color: String,
+}
+
+// Rows of the columns aligned on their widest value
+fn table(rows: &[Vec<String>]) -> Vec<String> {
+    let columns = rows.first().map(|r| r.len()).unwrap_or(0);
+    let widths: Vec<usize> = (0..columns)
+        .map(|i| rows.iter().map(|r| r[i].chars().count()).max().unwrap_or(0))
+        .collect();
+    rows.iter()
+        .map(|r| {
+            r.iter()
+                .zip(&widths)
+                .map(|(v, w)| format!("{v}{}", " ".repeat(w - v.chars().count())))
+                .collect::<Vec<String>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect()
+}
+
+fn tabs_command(conf: &config::Context, sub: &clap::ArgMatches) -> i32 {
+    let mut term = terminal::detect();
+    let tabs: Vec<OpenTab> = term
+        .list_tabs()
+        .into_iter()
+        .filter_map(|t| {
+            let (namespace, cluster, user) = t.context(&conf.tabprefix, &conf.separator)?;
+            Some(OpenTab {
+                window: t
+                    .id_path
+                    .rsplit_once('/')
+                    .map(|(w, _)| w.to_string())
+                    .unwrap_or_default(),
+                namespace: namespace.to_string(),
+                cluster: cluster.to_string(),
+                user: user.map(String::from),
+                kubeconfig: format!("{}/{}", conf.kubetmp, t.id_path),
+                color: conf
+                    .cluster_named(cluster)
+                    .map(|c| conf.tabcolor(c, user).active_bg)
+                    .unwrap_or_default(),
+                id: t.id,
+                title: t.title,
+                session: t.session,
+            })
+        })
+        .collect();
+    if sub.get_flag("json") {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&tabs).expect("Couldn't serialize tabs")
+        );
+        return 0;
+    }
+    let mut rows = vec![
+        [
+            "WINDOW",
+            "ID",
+            "TITLE",
+            "NAMESPACE",
+            "CLUSTER",
+            "USER",
+            "SESSION",
+            "COLOR",
+            "KUBECONFIG",
+        ]
+        .map(String::from)
+        .to_vec(),
+    ];
+    rows.extend(tabs.iter().map(|t| {
+        vec![
+            t.window.clone(),
+            t.id.clone(),
+            t.title.clone(),
+            t.namespace.clone(),
+            t.cluster.clone(),
+            t.user.clone().unwrap_or("-".to_string()),
+            t.session.clone().unwrap_or("-".to_string()),
+            t.color.clone(),
+            t.kubeconfig.clone(),
+        ]
+    }));
+    let lines = table(&rows);
+    if !sub.get_flag("pick") {
+        for line in lines {
+            println!("{line}");
+        }
+        return 0;
+    }
+    let picker = kube::Picker {
+        multi: false,
+        preview: None,
+        preview_window: conf.preview_window.clone(),
+    };
+    let choice = kube::selectable_list(lines[1..].to_vec(), Some(String::new()), &picker);
+    let Some(tab) = choice
+        .first()
+        .and_then(|c| lines[1..].iter().position(|l| l == c))
+        .map(|i| &tabs[i])
+    else {
+        debug!("Empty choice");
+        return 130;
+    };
+    if let Some(session) = &tab.session {
+        term.enter_session(session);
+    }
+    if !term.focus_tab_name(&tab.title) {
+        eprintln!("{}: tab not found", tab.title);
+        return 1;
+    }
+    0
+}
+
+fn snapshot_command(conf: &config::Context, sub: &clap::ArgMatches) -> i32 {
+    // Save or restore the ktk tabs, the errors are printed on stderr
+    let file = &conf.snapshot_filename;
+    let sep = &conf.separator;
+    let mut term = terminal::detect();
+    if sub.subcommand_name() == Some("save") {
+        let snapshot = snapshot::Snapshot::from_tabs(&term.list_tabs(), &conf.tabprefix, sep);
+        // Keep the previous snapshot, it may be the one to restore
+        if snapshot.tabs.is_empty() {
+            eprintln!("no ktk tab to save");
+            return 1;
+        }
+        if let Err(e) = snapshot.write(file) {
+            eprintln!("{e}");
+            return 1;
+        }
+        println!("{} tabs saved in {file}", snapshot.tabs.len());
+        return 0;
+    }
+    let snapshot = match snapshot::Snapshot::read(file) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{e}");
+            return 1;
+        }
+    };
+    let mut session = None;
+    let mut restored = 0;
+    for tab in &snapshot.tabs {
+        let mut choice = format!("{}{sep}{}", tab.namespace, tab.cluster);
+        if let Some(user) = &tab.user {
+            choice = format!("{choice}{sep}{user}");
+        }
+        if conf.cluster_named(&tab.cluster).is_none() {
+            eprintln!("{choice}: cluster {} not found in config file", tab.cluster);
+            continue;
+        }
+        if let Some(name) = &tab.session
+            && session.as_ref() != Some(name)
+        {
+            term.enter_session(name);
+            session = Some(name.clone());
+        }
+        if open_tab(
+            conf,
+            term.as_mut(),
+            &choice,
+            None,
+            false,
+            terminal::Layout::Tab,
+        ) {
+            restored += 1;
+        }
+    }
+    println!("{restored} tabs restored");
+    0
+}
+
+/// Selection of the cache entries shown in the fuzzy search
+#[derive(Clone)]
+struct CandidateFilter {
+    separator: String,
+    cluster: Option<String>, // kubens mode, only the namespaces of this cluster
+    subfilter: Regex,
+}
+
+impl CandidateFilter {
+    fn apply(&self, entry: &str) -> Option<String> {
+        match &self.cluster {
+            Some(cluster) => entry
+                .strip_suffix(format!("{}{}", self.separator, cluster).as_str())
+                .map(|v| v.to_string()),
+            None => self
+                .subfilter
+                .is_match(entry.as_bytes())
+                .then(|| entry.to_string()),
+        }
     }
 }
 
 fn possible_namespaces_in_context(conf: config::Context, cluster_search: String) -> Vec<String> {
+    let filter = CandidateFilter {
+        separator: conf.separator.clone(),
+        cluster: Some(cluster_search),
+        subfilter: Regex::new(".*").unwrap(),
+    };
     conf.read_completion_file()
         .split('\n')
-        .filter(|s| s.ends_with(format!("{}{}", conf.separator, cluster_search.clone()).as_str()))
-        .map(
-            |x| match x.strip_suffix(format!("{}{}", conf.separator, cluster_search).as_str()) {
-                Some(v) => v.to_string(),
-                None => "".to_string(),
-            },
-        )
+        .filter_map(|x| filter.apply(x))
         .collect()
 }
 
 fn possible_namespaces(conf: config::Context, regexsubfilter: Regex) -> Vec<String> {
+    let filter = CandidateFilter {
+        separator: conf.separator.clone(),
+        cluster: None,
+        subfilter: regexsubfilter,
+    };
+    // The aliases of the favorites are completed too
     conf.read_completion_file()
         .split('\n')
-        .filter(|s| regexsubfilter.captures(s.as_bytes()).is_some())
-        .map(|x| x.to_string())
+        .filter_map(|x| filter.apply(x))
+        .chain(conf.favorites.iter().filter_map(|f| f.alias.clone()))
         .collect()
 }
 
+fn stream_namespaces(
+    conf: &config::Context,
+    clusters: Vec<kube::Cluster>,
+    filter: CandidateFilter,
+    history: &history::History,
+) -> SkimItemReceiver {
+    // Feed the fuzzy search with the cache of the clusters which are not
+    // scanned, then with the namespaces of the others as soon as they answer.
+    // The namespaces of the last successful scan are used for the clusters
+    // that fail.
+    let (tx, rx): (SkimItemSender, SkimItemReceiver) = unbounded();
+    let scanned: Vec<String> = clusters
+        .iter()
+        .filter(|c| !c.disabled)
+        .map(|c| c.name.clone())
+        .collect();
+    let status = kube::ScanStatus::new(scanned.clone());
+    let _ = tx.send(Arc::new(status.clone()));
+    // The favorites are shown first
+    let pinned = conf.favorite_entries();
+    for entry in pinned.iter() {
+        if let Some(c) = filter.apply(entry) {
+            let _ = tx.send(Arc::new(c));
+        }
+    }
+    let previous = cache::Cache::read(&conf.completion_filename);
+    let others: Vec<String> = conf
+        .clusters_names()
+        .into_iter()
+        .filter(|n| !scanned.contains(n))
+        .collect();
+    let mut entries = previous.entries(&others, &conf.separator);
+    entries.retain(|e| !pinned.contains(e));
+    history.rank(&mut entries, &conf.separator);
+    for entry in entries {
+        if let Some(c) = filter.apply(&entry) {
+            let _ = tx.send(Arc::new(c));
+        }
+    }
+    let conf = conf.clone();
+    let history = history.clone();
+    // The thread is not joined: ktk exits once the tabs are opened, the
+    // clusters which have not answered yet are refreshed by the next ktk
+    thread::spawn(move || {
+        let on_result = |name: &str, result: &Result<Vec<String>, String>| {
+            status.done(name, result.is_ok());
+            let namespaces = match result {
+                Ok(ns) => ns.clone(),
+                Err(_) => previous
+                    .clusters
+                    .get(name)
+                    .map(|c| c.namespaces.clone())
+                    .unwrap_or_default(),
+            };
+            let mut entries: Vec<String> = namespaces
+                .iter()
+                .map(|ns| format!("{ns}{}{name}", conf.separator))
+                .filter(|e| !pinned.contains(e))
+                .collect();
+            history.rank(&mut entries, &conf.separator);
+            for entry in entries {
+                if let Some(c) = filter.apply(&entry) {
+                    let _ = tx.send(Arc::new(c));
+                }
+            }
+        };
+        if let Some(_lock) = cache::CacheLock::acquire(&conf.completion_filename) {
+            conf.update_completion_file(clusters, on_result);
+            return;
+        }
+        // Another ktk is refreshing the cache, its scan is used once done
+        debug!(
+            "Completion file {} is already being refreshed",
+            conf.completion_filename
+        );
+        drop(cache::CacheLock::wait(&conf.completion_filename));
+        let refreshed = cache::Cache::read(&conf.completion_filename);
+        for name in scanned {
+            let result = match refreshed.clusters.get(&name) {
+                Some(c) if c.error.is_none() => Ok(c.namespaces.clone()),
+                Some(c) => Err(c.error.clone().unwrap_or_default()),
+                None => Err(format!("{name} has not been scanned")),
+            };
+            on_result(&name, &result);
+        }
+    });
+    rx
+}
+
+// Check if the tab doesn't already exist.
+// If it exists, go to tab,
+// otherwise create a new one with its own kubeconfig file.
+// Returns true if a new tab was opened.
+// `kubens_cluster` is the cluster of a choice without cluster, only given
+// in kubens mode (-C).
+fn open_tab(
+    conf: &config::Context,
+    term: &mut dyn terminal::Terminal,
+    choice: &str,
+    kubens_cluster: Option<&str>,
+    in_current_tab: bool,
+    layout: terminal::Layout,
+) -> bool {
+    let tab_name = format!("{}{}", conf.tabprefix, choice);
+    if !layout.is_split() && term.focus_tab_name(&tab_name) {
+        info!("go to {choice}");
+        return false;
+    }
+    info!("launch {choice}");
+    // Get namespace arg
+    let s: Vec<&str> = choice.split(&conf.separator).collect();
+    if s.is_empty() {
+        process::exit(0);
+    }
+    let namespace = s[0];
+    let mut clustername = "".to_string();
+    let mut user = None;
+    if s.len() == 1
+        && let Some(cluster) = kubens_cluster
+    {
+        clustername = cluster.to_string()
+    }
+    if s.len() == 2 {
+        clustername = s[1].to_string();
+    }
+    if s.len() == 3 {
+        clustername = s[1].to_string();
+        user = Some(s[2]);
+    }
+    let cl = match conf.cluster_named(clustername.as_str()) {
+        Some(v) => v,
+        None => {
+            error!("cluster {clustername} not found in config file");
+            process::exit(7)
+        }
+    };
+    debug!("cluster name => {}", clustername.as_str());
+    if cl.protected && !confirm_protected(&cl.name) {
+        error!("{} is protected, {choice} is not opened", cl.name);
+        return false;
+    }
+    let destkubeconfig = format!("{}/{}", conf.kubetmp, term.identifier());
+    debug!("destination directory for kubeconfig files => {destkubeconfig}");
+    let mut kcf = match kubeconfig::Kubeconfig::new(cl.kubeconfig_path.clone()) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("error parsing file {}: {e:?}", cl.kubeconfig_path);
+            process::exit(6)
+        }
+    };
+    if let Some(context) = &cl.context
+        && !kcf.select_context(context)
+    {
+        error!("context {context} not found in {}", cl.kubeconfig_path);
+        process::exit(6)
+    }
+    debug!("change kube context => {}", namespace);
+    kcf.change_context(namespace.to_string());
+    kcf.set_ktk_cluster(&cl.name);
+    // Only the default user of the tabs impersonates another one
+    let impersonate = cl.impersonate.as_deref().filter(|_| user.is_none());
+    // The tabs of an elevated user, or without impersonation, stand out
+    let tabcolor = conf.tabcolor(cl, user);
+    if let Some(user) = user.or(cl.default_user.as_deref())
+        && !kcf.change_user(user)
+    {
+        error!("user {user} not found in {}", cl.kubeconfig_path);
+        process::exit(6)
+    }
+    if let Some(name) = impersonate
+        && !kcf.impersonate(name)
+    {
+        error!(
+            "user {} of the context not found in {}",
+            kcf.user_context(),
+            cl.kubeconfig_path
+        );
+        process::exit(6)
+    }
+    debug!("kube user => {}", kcf.user_context());
+    if in_current_tab {
+        debug!("change tab title => {tab_name}");
+        term.change_tab_title(&tab_name);
+        term.change_tab_color(tabcolor);
+        println!();
+        let tab_id = term.id_of_focus_tab().unwrap();
+        debug!("tab_id => {}", tab_id);
+        // The file of the pane or of the tab is changed in place, the link
+        // is followed so that the shell launched with it sees the change
+        let pane = term.id_of_focus_pane().map(|id| format!("pane-{id}"));
+        let kubefile = [pane, Some(tab_id.clone())]
+            .into_iter()
+            .flatten()
+            .map(|f| PathBuf::from(format!("{destkubeconfig}/{f}")))
+            .find(|f| f.exists())
+            .and_then(|f| fs::canonicalize(f).ok())
+            .unwrap_or_else(|| PathBuf::from(format!("{destkubeconfig}/{tab_id}")));
+        debug!("write new kubeconfig in {}", kubefile.display());
+        kcf.write(
+            kubefile.parent().unwrap().display().to_string(),
+            kubefile.file_name().unwrap().to_string_lossy().to_string(),
+        );
+        return true;
+    }
+    // The kubeconfig file is written before the tab is created, so that
+    // its shell starts with KUBECONFIG set and in the working directory.
+    // Each tab has its own file, even with the same context.
+    let filename = match kubeconfig::reserve(&destkubeconfig, &context_filename(choice)) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Couldn't create a kubeconfig file in {destkubeconfig}: {e}");
+            process::exit(1)
+        }
+    };
+    debug!("write new kubeconfig in {}/{}", destkubeconfig, filename);
+    kcf.write(destkubeconfig.clone(), filename.clone());
+    let kubeconfig = format!("{destkubeconfig}/{filename}");
+    let workdir = kube::ns_dir(cl, namespace);
+    if layout == terminal::Layout::Tab {
+        debug!("create new tab => {tab_name} in {workdir}");
+        term.create_new_tab(&tab_name, &kubeconfig, &workdir);
+    } else {
+        debug!("create new pane {layout:?} => {tab_name} in {workdir}");
+        let pane_id = match term.create_new_pane(layout, &tab_name, &kubeconfig, &workdir) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("{e}");
+                process::exit(42)
+            }
+        };
+        // `ktk --evaldir` finds the kubeconfig file of a pane with its id,
+        // before the one of its tab
+        link_kubeconfig(conf, term, &kubeconfig, &format!("pane-{pane_id}"));
+        if layout.is_split() {
+            // The tab keeps the name and the colors of its namespace
+            println!();
+            return true;
+        }
+    }
+    term.change_tab_color(tabcolor);
+    println!();
+    let tab_id = match term.id_of_tab_name(&tab_name) {
+        Some(tab_id) => {
+            term.focus_tab_name(&tab_name);
+            tab_id
+        }
+        None => term.id_of_focus_tab().unwrap(),
+    };
+    // `ktk --evaldir` finds the kubeconfig file of a tab with its id
+    debug!("tab_id => {}", tab_id);
+    link_kubeconfig(conf, term, &kubeconfig, &tab_id);
+    true
+}
+
+// The name of a protected cluster must be typed to open a tab on it, the
+// question is asked on the terminal even if stdin is redirected
+fn confirm_protected(cluster: &str) -> bool {
+    let mut tty = match OpenOptions::new().read(true).write(true).open("/dev/tty") {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{cluster} is protected and can't be confirmed without a terminal: {e}");
+            return false;
+        }
+    };
+    if write!(tty, "{cluster} is protected, type its name to confirm: ").is_err() {
+        return false;
+    }
+    let mut answer = String::new();
+    match io::BufReader::new(tty).read_line(&mut answer) {
+        Ok(_) => answer.trim() == cluster,
+        Err(_) => false,
+    }
+}
+
+// The kubeconfig files are linked in the directory of the terminal window,
+// which is not the one of ktk for a new OS window
+fn link_kubeconfig(
+    conf: &config::Context,
+    term: &dyn terminal::Terminal,
+    kubeconfig: &str,
+    filename: &str,
+) {
+    let dest = format!("{}/{}", conf.kubetmp, term.identifier());
+    if let Err(e) =
+        fs::create_dir_all(&dest).and_then(|_| kubeconfig::link(&dest, kubeconfig, filename))
+    {
+        warn!("Couldn't link the kubeconfig file {dest}/{filename}: {e}");
+    }
+    // The files of the closed tabs and panes are removed, the terminal is
+    // not pruned if it can't list its tabs
+    let tabs = term.list_tabs();
+    if tabs.is_empty() {
+        return;
+    }
+    let panes = term.list_panes();
+    let identifier = term.identifier();
+    let is_open = |name: &str| match name.strip_prefix("pane-") {
+        Some(id) => panes.as_ref().is_none_or(|p| p.iter().any(|p| p == id)),
+        None => tabs
+            .iter()
+            .any(|t| t.id_path == format!("{identifier}/{name}")),
+    };
+    if let Err(e) = kubeconfig::prune(&conf.kubetmp, &dest, PRUNE_GRACE, is_open) {
+        warn!("Couldn't remove the kubeconfig files of the closed tabs in {dest}: {e}");
+    }
+}
+
+// Age of the links and of the kubeconfig files which are never pruned, the
+// tab of a file reserved by another ktk may not be linked or listed yet
+const PRUNE_GRACE: Duration = Duration::from_secs(60);
+
+// Prefix of the kubeconfig files of a namespace, usable in a file name
+fn context_filename(choice: &str) -> String {
+    format!("ctx-{}", terminal::file_id(choice))
+}
+
 fn main() -> Result<(), io::Error> {
     // load clap config
     let matches = clap_command(Vec::new(), Vec::new()).get_matches();
@@ -326,6 +1308,12 @@ fn main() -> Result<(), io::Error> {
         configlog(false);
     }
 
+    // The Lua handler doesn't depend on the configuration
+    if matches.subcommand_name() == Some("wezterm-lua") {
+        print!("{}", terminal::wezterm::TAB_TITLE_LUA);
+        process::exit(0)
+    }
+
     // Checking the presence of the configuration file
     let config_path = match matches.get_one::<PathBuf>("config") {
         Some(v) => v,
@@ -341,9 +1329,36 @@ fn main() -> Result<(), io::Error> {
         process::exit(52)
     }
 
+    if let Some(("config", sub)) = matches.subcommand()
+        && sub.subcommand_name() == Some("validate")
+    {
+        process::exit(validate_config(config_path))
+    }
+
     // Load yaml config file
     let conf = config::Context::new(config_path, matches.get_flag("wait"));
 
+    if let Some(("fav", sub)) = matches.subcommand() {
+        process::exit(favorites_command(&conf, sub))
+    }
+
+    if let Some(("session", sub)) = matches.subcommand() {
+        process::exit(session_command(&conf, sub))
+    }
+
+    if let Some(("tabs", sub)) = matches.subcommand() {
+        process::exit(tabs_command(&conf, sub))
+    }
+
+    if let Some(("snapshot", sub)) = matches.subcommand() {
+        process::exit(snapshot_command(&conf, sub))
+    }
+
+    if let Some(("preview", sub)) = matches.subcommand() {
+        let entry = sub.get_one::<String>("entry").unwrap();
+        process::exit(preview(&conf, entry))
+    }
+
     if matches.get_flag("list-clusters-names") {
         conf.list_clusters_names();
         process::exit(0)
@@ -364,12 +1379,31 @@ fn main() -> Result<(), io::Error> {
         process::exit(0)
     }
 
+    if matches.get_flag("background-refresh") {
+        // Only one ktk refreshes the cache at a time
+        if let Some(_lock) = cache::CacheLock::acquire(&conf.completion_filename) {
+            let clusters = conf.outdated_clusters();
+            if !clusters.is_empty() {
+                conf.update_completion_file(clusters, |_, _| {});
+            }
+        }
+        process::exit(0)
+    }
+
     let mut term = terminal::detect();
     // Initialize user input namespace
-    let namespace_search = match matches.get_one::<String>("namespace") {
+    let mut namespace_search = match matches.get_one::<String>("namespace") {
         Some(v) => v.to_string(),
         None => "".to_string(),
     };
+    // The user can be given after the cluster: namespace::cluster::user
+    let mut user = matches.get_one::<String>("user").cloned();
+    if namespace_search.matches(conf.separator.as_str()).count() == 2
+        && let Some((entry, u)) = namespace_search.rsplit_once(conf.separator.as_str())
+    {
+        user = Some(u.to_string());
+        namespace_search = entry.to_string();
+    }
 
     let mut cluster_search = "".to_string();
 
@@ -379,7 +1413,7 @@ fn main() -> Result<(), io::Error> {
         match env::var("KUBECONFIG") {
             Ok(kc) => {
                 cluster_search = match kubeconfig::Kubeconfig::new(kc.clone()) {
-                    Ok(v) => v.cluster_context(),
+                    Ok(v) => v.ktk_cluster().unwrap_or_else(|| v.cluster_context()),
                     Err(e) => {
                         if !matches.contains_id("completion") {
                             error!("error parsing file {}: {e:?}", kc);
@@ -398,14 +1432,52 @@ fn main() -> Result<(), io::Error> {
         };
     }
 
-    // Check if the completion file must be update
-    if !matches.get_flag("noscan")
-        && (conf.completion_file_older_than_maxage()
-            || conf.completion_file_older_than_config()
-            || matches.get_flag("force"))
-    {
-        debug!("Update completion file {}", conf.completion_filename);
-        conf.update_completion_file();
+    // Check if the completion file must be update.
+    // Only a missing cache or the force option need a scan before the search,
+    // otherwise the outdated clusters are refreshed in background.
+    // The search is fed while the clusters are scanned, unless a complete
+    // namespace and cluster is given or for the shell completion.
+    // A favorite alias opens its namespace directly, without the cache
+    let favorite = match matches.get_flag("cluster") {
+        true => None,
+        false => conf.favorite_by_alias(&namespace_search),
+    };
+    let mut scan = None;
+    if !matches.get_flag("noscan") && !matches.get_flag("recent") && favorite.is_none() {
+        let clusters = match matches.get_flag("force") {
+            true => conf.clusters.clone(),
+            false => conf.outdated_clusters(),
+        };
+        if clusters.is_empty() {
+            debug!("Completion file {} is up to date", conf.completion_filename);
+        } else if matches.get_flag("force") || !Path::new(&conf.completion_filename).exists() {
+            if matches.contains_id("completion") || namespace_search.contains(&conf.separator) {
+                debug!("Update completion file {}", conf.completion_filename);
+                // Wait for the refresh of another ktk, the clusters it
+                // scanned are not scanned again
+                let _lock = cache::CacheLock::wait(&conf.completion_filename);
+                let clusters = match matches.get_flag("force") {
+                    true => clusters,
+                    false => conf.outdated_clusters(),
+                };
+                conf.update_completion_file(clusters, |_, _| {});
+            } else {
+                scan = Some(clusters);
+            }
+        } else if cache::CacheLock::is_locked(&conf.completion_filename) {
+            debug!(
+                "Completion file {} is already being refreshed",
+                conf.completion_filename
+            );
+        } else {
+            refresh_in_background(config_path, &matches);
+        }
+    }
+    // A favorite alias doesn't touch the cache
+    if favorite.is_none() {
+        for (name, e) in conf.stale_clusters() {
+            info!("last scan of {name} failed, its namespaces may be outdated: {e}");
+        }
     }
 
     let subfilter_env = match env::var("KTKSUBFILTER") {
@@ -430,84 +1502,105 @@ fn main() -> Result<(), io::Error> {
 
     // Show fuzzy search to choose the namespace
     // In kubens mode, we only display the namespace, not the cluster name
-    let choice = if matches.get_flag("cluster") {
-        format!(
-            "{}{}{}",
+    let filter = CandidateFilter {
+        separator: conf.separator.clone(),
+        cluster: matches.get_flag("cluster").then(|| cluster_search.clone()),
+        subfilter: regexsubfilter,
+    };
+    let mut picker = kube::Picker {
+        multi: matches.get_flag("multi"),
+        preview: None,
+        preview_window: conf.preview_window.clone(),
+    };
+    if conf.preview || matches.get_flag("preview") {
+        let suffix = match matches.get_flag("cluster") {
+            true => format!("{}{}", conf.separator, cluster_search),
+            false => "".to_string(),
+        };
+        picker.preview = Some(preview_command(config_path, &suffix));
+    }
+    // The most frecent namespaces are shown first
+    let history = history::History::read(&conf.history_filename);
+    let choices = match (favorite, scan) {
+        (Some(entry), _) => {
+            debug!("favorite {namespace_search} => {entry}");
+            vec![entry]
+        }
+        (None, Some(clusters)) => {
+            debug!(
+                "Update completion file {} during the search",
+                conf.completion_filename
+            );
+            let items = stream_namespaces(&conf, clusters, filter, &history);
+            TERM_LOG_MUTED.store(true, Ordering::Relaxed);
+            let choices = kube::selectable_stream(items, Some(namespace_search), &picker);
+            TERM_LOG_MUTED.store(false, Ordering::Relaxed);
+            choices
+        }
+        (None, None) => {
+            // The favorites are shown first
+            let pinned = conf.favorite_entries();
+            let mut entries = match matches.get_flag("recent") {
+                true => history.recent(&conf.clusters_names(), &conf.separator),
+                false => {
+                    let mut entries: Vec<String> = conf
+                        .read_completion_file()
+                        .split('\n')
+                        .map(String::from)
+                        .collect();
+                    history.rank(&mut entries, &conf.separator);
+                    entries
+                }
+            };
+            entries.retain(|e| !pinned.contains(e));
             kube::selectable_list(
-                possible_namespaces_in_context(conf.clone(), cluster_search.clone()),
+                pinned
+                    .iter()
+                    .chain(entries.iter())
+                    .filter_map(|x| filter.apply(x))
+                    .collect(),
                 Some(namespace_search),
-            ),
-            conf.separator,
-            cluster_search
-        )
-    } else {
-        kube::selectable_list(
-            possible_namespaces(conf.clone(), regexsubfilter),
-            Some(namespace_search),
-        )
+                &picker,
+            )
+        }
     };
-    if choice.is_empty() {
-        debug!("Empty choice");
-        process::exit(130);
+    let mut selected = Vec::new();
+    for mut choice in choices {
+        if matches.get_flag("cluster") {
+            choice = format!("{}{}{}", choice, conf.separator, cluster_search);
+        }
+        if choice.is_empty() {
+            debug!("Empty choice");
+            process::exit(130);
+        }
+        let visit = choice
+            .split_once(&conf.separator)
+            .map(|(namespace, cluster)| (namespace.to_string(), cluster.to_string()));
+        if let Some(user) = &user {
+            choice = format!("{choice}{}{user}", conf.separator);
+        }
+        selected.push((choice, visit));
     }
-    // Check if the tab doesn't already exist.
-    // If it exists, go to tab,
-    // otherwise create a new one.
-    let tab_name = format!("{}{}", conf.tabprefix, &choice);
-    if term.focus_tab_name(&tab_name) {
-        info!("go to {choice}");
-    } else {
-        info!("launch {choice}");
-        // Get namespace arg
-        let s: Vec<&str> = choice.split(&conf.separator).collect();
-        if s.is_empty() {
-            process::exit(0);
-        }
-        let namespace = s[0];
-        let mut clustername = "".to_string();
-        if s.len() == 1 && matches.get_flag("cluster") {
-            clustername = cluster_search
-        }
-        if s.len() == 2 {
-            clustername = s[1].to_string();
-        }
-        if !matches.get_flag("tab") {
-            debug!("create new tab => {tab_name}");
-            term.create_new_tab(&tab_name);
-        } else {
-            debug!("change tab title => {tab_name}");
-            term.change_tab_title(&tab_name);
-        }
-        let cl = conf.cluster_named(clustername.as_str()).unwrap();
-        debug!("cluster name => {}", clustername.as_str());
-        let destkubeconfig = format!("{}/{}", conf.kubetmp, term.identifier());
-        debug!("destination directory for kubeconfig files => {destkubeconfig}");
-        term.change_tab_color(cl.tabcolor.clone());
-        println!();
-        let mut kcf = match kubeconfig::Kubeconfig::new(cl.kubeconfig_path.clone()) {
-            Ok(v) => v,
-            Err(e) => {
-                error!("error parsing file {}: {e:?}", cl.kubeconfig_path);
-                process::exit(6)
-            }
-        };
-        debug!("change kube context => {}", namespace);
-        kcf.change_context(namespace.to_string());
-        match term.id_of_tab_name(&tab_name) {
-            Some(tab_id) => {
-                debug!("tab_id => {}", tab_id);
-                debug!("write new kubeconfig in {}/{}", destkubeconfig, tab_id);
-                kcf.write(destkubeconfig, tab_id);
-                term.focus_tab_name(&tab_name);
-            }
-            None => {
-                let tab_id = term.id_of_focus_tab().unwrap();
-                debug!("tab_id => {}", tab_id);
-                debug!("write new kubeconfig in {}/{}", destkubeconfig, tab_id);
-                kcf.write(destkubeconfig, tab_id);
-            }
+    let layout = terminal::Layout::from_name(matches.get_one::<String>("layout").unwrap());
+    // Only the namespaces opened are recorded, not the protected ones
+    // whose confirmation was refused
+    let mut visits = Vec::new();
+    for (choice, visit) in selected {
+        let opened = open_tab(
+            &conf,
+            term.as_mut(),
+            &choice,
+            matches
+                .get_flag("cluster")
+                .then_some(cluster_search.as_str()),
+            matches.get_flag("tab"),
+            layout,
+        );
+        if opened {
+            visits.extend(visit);
         }
     }
+    history::History::save_visits(&conf.history_filename, &visits);
 
     Ok(())
 }
@@ -0,0 +1,114 @@
+//! Snapshot of the ktk tabs of the terminal, to open them again after a
+//! crash of the terminal or a reboot
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::terminal::TabInfo;
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    #[serde(default)]
+    pub tabs: Vec<SnapshotTab>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotTab {
+    pub namespace: String,
+    pub cluster: String,
+    // user of the kubeconfig, when it is not the default one of the cluster
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    // tmux session or wezterm workspace of the tab
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+}
+
+impl Snapshot {
+    // Keeps the tabs opened by ktk
+    pub fn from_tabs(tabs: &[TabInfo], tabprefix: &str, sep: &str) -> Snapshot {
+        let mut snapshot = Snapshot::default();
+        for tab in tabs {
+            let Some((namespace, cluster, user)) = tab.context(tabprefix, sep) else {
+                continue;
+            };
+            let entry = SnapshotTab {
+                namespace: namespace.to_string(),
+                cluster: cluster.to_string(),
+                user: user.map(String::from),
+                session: tab.session.clone(),
+            };
+            if !snapshot.tabs.contains(&entry) {
+                snapshot.tabs.push(entry);
+            }
+        }
+        snapshot
+    }
+
+    pub fn read(path: &str) -> Result<Snapshot, String> {
+        let content = fs::read(path).map_err(|e| format!("{path}: {e}"))?;
+        serde_json::from_slice(&content).map_err(|e| format!("{path}: {e}"))
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), String> {
+        if let Some(parent) = Path::new(path).parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        let tmp = format!("{path}.tmp");
+        let content = serde_json::to_vec_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(&tmp, content)
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(|e| format!("{path}: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Snapshot, SnapshotTab};
+    use crate::terminal::TabInfo;
+
+    fn tab(title: &str, session: Option<&str>) -> TabInfo {
+        TabInfo {
+            id: "1".to_string(),
+            title: title.to_string(),
+            session: session.map(String::from),
+            id_path: "main/1".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_from_tabs() {
+        let tabs = vec![
+            tab("bash", Some("main")),
+            tab("☸>>kube-system::prod", Some("main")),
+            tab("☸>>kube-system::prod", Some("main")),
+            tab("☸>>web::dev", None),
+            tab("☸>>web::dev::admin", None),
+            tab("☸>>no-cluster", None),
+        ];
+        let snapshot = Snapshot::from_tabs(&tabs, "☸>>", "::");
+        assert_eq!(
+            snapshot.tabs,
+            vec![
+                SnapshotTab {
+                    namespace: "kube-system".to_string(),
+                    cluster: "prod".to_string(),
+                    user: None,
+                    session: Some("main".to_string()),
+                },
+                SnapshotTab {
+                    namespace: "web".to_string(),
+                    cluster: "dev".to_string(),
+                    user: None,
+                    session: None,
+                },
+                SnapshotTab {
+                    namespace: "web".to_string(),
+                    cluster: "dev".to_string(),
+                    user: Some("admin".to_string()),
+                    session: None,
+                },
+            ]
+        );
+    }
+}
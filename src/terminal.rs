@@ -1,6 +1,8 @@
 pub mod kitty;
+pub mod screen;
 pub mod tmux;
 pub mod wezterm;
+pub mod zellij;
 use std::{env, process};
 
 use log::{debug, error};
@@ -17,6 +19,73 @@ pub struct WezTerm {
     context: wezterm::Context,
 }
 
+pub struct Screen {
+    context: screen::Context,
+}
+
+pub struct Zellij {
+    context: zellij::Context,
+}
+
+/// Tab of the terminal, the session is the tmux session or the wezterm
+/// workspace of the tab.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TabInfo {
+    pub id: String,
+    pub title: String,
+    pub session: Option<String>,
+    pub id_path: String, // path of its kubeconfig file in kubetmp
+}
+
+impl TabInfo {
+    // Returns the namespace, the cluster and the user of a tab opened by
+    // ktk, its title is the tab prefix followed by `namespace<sep>cluster`,
+    // then `<sep>user` when it was opened with another user
+    pub fn context(&self, tabprefix: &str, sep: &str) -> Option<(&str, &str, Option<&str>)> {
+        let (namespace, rest) = self.title.strip_prefix(tabprefix)?.split_once(sep)?;
+        match rest.split_once(sep) {
+            Some((cluster, user)) => Some((namespace, cluster, Some(user))),
+            None => Some((namespace, rest, None)),
+        }
+    }
+}
+
+// The id paths of the contexts are prefixed like the ones of the focused tab
+fn prefix_id_paths(tabs: Vec<TabInfo>, terminal: &str) -> Vec<TabInfo> {
+    tabs.into_iter()
+        .map(|t| TabInfo {
+            id_path: format!("{terminal}-{}{}", ktk_env(), t.id_path),
+            ..t
+        })
+        .collect()
+}
+
+/// Where a namespace is opened.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layout {
+    Tab,
+    VSplit, // pane beside the current one
+    HSplit, // pane below the current one
+    Window, // new OS window
+}
+
+impl Layout {
+    pub const NAMES: [&'static str; 4] = ["tab", "vsplit", "hsplit", "window"];
+
+    pub fn from_name(name: &str) -> Layout {
+        match name {
+            "vsplit" => Layout::VSplit,
+            "hsplit" => Layout::HSplit,
+            "window" => Layout::Window,
+            _ => Layout::Tab,
+        }
+    }
+
+    pub fn is_split(&self) -> bool {
+        matches!(self, Layout::VSplit | Layout::HSplit)
+    }
+}
+
 #[allow(dead_code)]
 pub trait Terminal {
     fn good_term(&self) -> bool;
@@ -26,7 +95,25 @@ pub trait Terminal {
     fn id_path_of_focus_tab(&self) -> Option<String>;
     fn focus_tab_name(&self, name: &str) -> bool;
     fn focus_execute_tab(&mut self);
-    fn create_new_tab(&mut self, name: &str);
+    fn create_new_tab(&mut self, name: &str, kubeconfig: &str, dir: &str);
+    // Returns the id of the new pane, the layout is not a tab
+    fn create_new_pane(
+        &mut self,
+        layout: Layout,
+        name: &str,
+        kubeconfig: &str,
+        dir: &str,
+    ) -> Result<String, String>;
+    fn id_of_focus_pane(&self) -> Option<String>;
+    // Opens the next tabs in the session `name` of the terminal (tmux
+    // session, wezterm workspace), returns false if it has no sessions
+    fn enter_session(&mut self, name: &str) -> bool;
+    // Types a command in the shell of the last created tab
+    fn send_command(&self, command: &str);
+    // Returns the tabs of all the windows and sessions of the terminal
+    fn list_tabs(&self) -> Vec<TabInfo>;
+    // Returns the ids of all the panes, None if the terminal has no panes
+    fn list_panes(&self) -> Option<Vec<String>>;
     fn change_tab_title(&self, name: &str);
     fn change_tab_color(&self, color: kitty::Tabcolor);
 }
@@ -35,9 +122,45 @@ pub fn ktk_env() -> String {
     env::var("KTKENV").unwrap_or("".to_string())
 }
 
+/// Name usable as a file name in a shell command, the other chars are
+/// percent-encoded so that two names never give the same file.
+pub fn file_id(name: &str) -> String {
+    let mut id = String::new();
+    for b in name.bytes() {
+        match b.is_ascii_alphanumeric() || b == b'-' || b == b'_' {
+            true => id.push(b as char),
+            false => id.push_str(&format!("%{b:02X}")),
+        }
+    }
+    id
+}
+
+// Environment variable given to the shell of a new tab
+fn kubeconfig_env(kubeconfig: &str) -> String {
+    match kubeconfig.is_empty() {
+        true => String::new(),
+        false => format!("KUBECONFIG={kubeconfig}"),
+    }
+}
+
 pub fn detect() -> Box<dyn Terminal> {
     let other = "other".to_string();
-    match env::var("TERM_PROGRAM").unwrap_or(other.clone()).as_str() {
+    let program = env::var("TERM_PROGRAM").unwrap_or(other.clone());
+    // Zellij and GNU screen keep the TERM_PROGRAM of the terminal they run
+    // in, unlike tmux which may run in them
+    if program != "tmux" && env::var("ZELLIJ").is_ok() {
+        debug!("Zellij terminal");
+        return Box::new(Zellij {
+            context: zellij::Context::new(),
+        });
+    }
+    if program != "tmux" && env::var("STY").is_ok() {
+        debug!("Screen terminal");
+        return Box::new(Screen {
+            context: screen::Context::new(),
+        });
+    }
+    match program.as_str() {
         "tmux" => {
             debug!("Tmux terminal");
             Box::new(Tmux {
@@ -57,7 +180,7 @@ pub fn detect() -> Box<dyn Terminal> {
                     context: kitty::Context::new(),
                 })
             } else {
-                error!("Only supports Kitty, WezTerm and Tmux for now.");
+                error!("Only supports Kitty, WezTerm, Tmux, Zellij and GNU screen for now.");
                 process::exit(42)
             }
         }
@@ -99,8 +222,47 @@ impl Terminal for Kitty {
         self.context.focus_execute_tab();
     }
 
-    fn create_new_tab(&mut self, name: &str) {
-        self.context.launch_shell_in_new_tab_name(name);
+    fn create_new_tab(&mut self, name: &str, kubeconfig: &str, dir: &str) {
+        self.context
+            .launch_shell_in_new_tab_name(name, dir, &kubeconfig_env(kubeconfig));
+    }
+
+    fn create_new_pane(
+        &mut self,
+        layout: Layout,
+        name: &str,
+        kubeconfig: &str,
+        dir: &str,
+    ) -> Result<String, String> {
+        let (kind, location) = match layout {
+            Layout::VSplit => ("window", Some("vsplit")),
+            Layout::HSplit => ("window", Some("hsplit")),
+            _ => ("os-window", None),
+        };
+        self.context
+            .launch_shell_in_new_window(kind, location, name, dir, &kubeconfig_env(kubeconfig))
+            .ok_or("Failed to launch a new kitty window".to_string())
+    }
+
+    fn id_of_focus_pane(&self) -> Option<String> {
+        env::var("KITTY_WINDOW_ID").ok()
+    }
+
+    fn enter_session(&mut self, _: &str) -> bool {
+        false
+    }
+
+    fn send_command(&self, command: &str) {
+        self.context
+            .send_text_to_last_window(&format!("{command}\r"));
+    }
+
+    fn list_tabs(&self) -> Vec<TabInfo> {
+        prefix_id_paths(self.context.tabs(), "kitty")
+    }
+
+    fn list_panes(&self) -> Option<Vec<String>> {
+        Some(self.context.panes())
     }
 
     fn change_tab_title(&self, name: &str) {
@@ -141,15 +303,56 @@ impl Terminal for Tmux {
 
     fn focus_execute_tab(&mut self) {}
 
-    fn create_new_tab(&mut self, name: &str) {
-        self.context.launch_shell_in_new_tab_name(name);
+    fn create_new_tab(&mut self, name: &str, kubeconfig: &str, dir: &str) {
+        self.context
+            .launch_shell_in_new_tab_name(name, dir, &kubeconfig_env(kubeconfig));
+    }
+
+    fn create_new_pane(
+        &mut self,
+        layout: Layout,
+        _: &str,
+        kubeconfig: &str,
+        dir: &str,
+    ) -> Result<String, String> {
+        let direction = match layout {
+            Layout::VSplit => "-h",
+            Layout::HSplit => "-v",
+            _ => return Err("tmux can't open an OS window".to_string()),
+        };
+        self.context
+            .launch_shell_in_new_pane(direction, dir, &kubeconfig_env(kubeconfig))
+            .ok_or("Failed to split the tmux window".to_string())
+    }
+
+    fn id_of_focus_pane(&self) -> Option<String> {
+        env::var("TMUX_PANE").ok()
+    }
+
+    fn enter_session(&mut self, name: &str) -> bool {
+        self.context.enter_session(name);
+        true
+    }
+
+    fn send_command(&self, command: &str) {
+        self.context.send_keys_to_last_window(command);
+    }
+
+    fn list_tabs(&self) -> Vec<TabInfo> {
+        prefix_id_paths(self.context.windows(), "tmux")
+    }
+
+    fn list_panes(&self) -> Option<Vec<String>> {
+        Some(self.context.panes())
     }
 
     fn change_tab_title(&self, name: &str) {
         self.context.set_tab_title(name);
     }
 
-    fn change_tab_color(&self, _: kitty::Tabcolor) {}
+    fn change_tab_color(&self, color: kitty::Tabcolor) {
+        self.context.set_tab_color(&color);
+    }
 }
 
 impl Terminal for WezTerm {
@@ -187,8 +390,118 @@ impl Terminal for WezTerm {
         self.context.focus_execute_pane();
     }
 
-    fn create_new_tab(&mut self, name: &str) {
-        self.context.launch_shell_in_new_tab_name(name);
+    fn create_new_tab(&mut self, name: &str, kubeconfig: &str, dir: &str) {
+        self.context
+            .launch_shell_in_new_tab_name(name, dir, &kubeconfig_env(kubeconfig));
+    }
+
+    fn create_new_pane(
+        &mut self,
+        layout: Layout,
+        name: &str,
+        kubeconfig: &str,
+        dir: &str,
+    ) -> Result<String, String> {
+        let args = match layout {
+            Layout::VSplit => vec!["split-pane", "--horizontal"],
+            Layout::HSplit => vec!["split-pane", "--bottom"],
+            _ => vec!["spawn", "--new-window"],
+        };
+        let pane_id = self
+            .context
+            .launch_shell_in_new_pane(&args, dir, &kubeconfig_env(kubeconfig))
+            .ok_or("Failed to create a wezterm pane".to_string())?;
+        if layout == Layout::Window {
+            self.context.set_tab_title_for_pane_id(name, &pane_id);
+        }
+        Ok(pane_id)
+    }
+
+    fn id_of_focus_pane(&self) -> Option<String> {
+        env::var("WEZTERM_PANE").ok()
+    }
+
+    fn enter_session(&mut self, name: &str) -> bool {
+        self.context.enter_workspace(name);
+        true
+    }
+
+    fn send_command(&self, command: &str) {
+        self.context.send_text_to_last_pane(&format!("{command}\r"));
+    }
+
+    fn list_tabs(&self) -> Vec<TabInfo> {
+        prefix_id_paths(self.context.tabs(), "wezterm")
+    }
+
+    fn list_panes(&self) -> Option<Vec<String>> {
+        Some(self.context.panes())
+    }
+
+    fn change_tab_title(&self, name: &str) {
+        self.context.set_tab_title(name);
+    }
+
+    fn change_tab_color(&self, color: kitty::Tabcolor) {
+        self.context.set_tab_color(&color);
+    }
+}
+
+impl Terminal for Zellij {
+    fn good_term(&self) -> bool {
+        self.context.good_term()
+    }
+
+    fn identifier(&self) -> String {
+        format!("zellij-{}{}", ktk_env(), self.context.session_name())
+    }
+
+    fn id_of_focus_tab(&self) -> Option<String> {
+        self.context.id_of_focus_tab()
+    }
+
+    fn id_of_tab_name(&self, name: &str) -> Option<String> {
+        self.context.id_of_tab_name(name)
+    }
+
+    fn id_path_of_focus_tab(&self) -> Option<String> {
+        self.context
+            .id_path_of_focus_tab()
+            .map(|expr| format!("zellij-{}{}", ktk_env(), expr))
+    }
+
+    fn focus_tab_name(&self, name: &str) -> bool {
+        self.context.go_to_tab_name(name)
+    }
+
+    fn focus_execute_tab(&mut self) {}
+
+    fn create_new_tab(&mut self, name: &str, _: &str, dir: &str) {
+        self.context.launch_shell_in_new_tab_name(name, dir);
+    }
+
+    fn create_new_pane(&mut self, _: Layout, _: &str, _: &str, _: &str) -> Result<String, String> {
+        Err("zellij only supports tabs".to_string())
+    }
+
+    fn id_of_focus_pane(&self) -> Option<String> {
+        None
+    }
+
+    fn enter_session(&mut self, _: &str) -> bool {
+        false
+    }
+
+    fn send_command(&self, command: &str) {
+        self.context.write_chars(&format!("{command}\r"));
+    }
+
+    fn list_tabs(&self) -> Vec<TabInfo> {
+        prefix_id_paths(self.context.tabs(), "zellij")
+    }
+
+    fn list_panes(&self) -> Option<Vec<String>> {
+        None
     }
 
     fn change_tab_title(&self, name: &str) {
@@ -197,3 +510,68 @@ impl Terminal for WezTerm {
 
     fn change_tab_color(&self, _: kitty::Tabcolor) {}
 }
+
+impl Terminal for Screen {
+    fn good_term(&self) -> bool {
+        self.context.good_term()
+    }
+
+    fn identifier(&self) -> String {
+        format!("screen-{}{}", ktk_env(), self.context.session_name())
+    }
+
+    fn id_of_focus_tab(&self) -> Option<String> {
+        self.context.id_of_current_window()
+    }
+
+    fn id_of_tab_name(&self, name: &str) -> Option<String> {
+        self.context.id_of_window_title(name)
+    }
+
+    fn id_path_of_focus_tab(&self) -> Option<String> {
+        self.context
+            .id_path_of_current_window()
+            .map(|expr| format!("screen-{}{}", ktk_env(), expr))
+    }
+
+    fn focus_tab_name(&self, name: &str) -> bool {
+        self.context.select_window_title(name)
+    }
+
+    fn focus_execute_tab(&mut self) {}
+
+    fn create_new_tab(&mut self, name: &str, kubeconfig: &str, dir: &str) {
+        self.context
+            .launch_shell_in_new_window_title(name, dir, &kubeconfig_env(kubeconfig));
+    }
+
+    fn create_new_pane(&mut self, _: Layout, _: &str, _: &str, _: &str) -> Result<String, String> {
+        Err("screen only supports tabs".to_string())
+    }
+
+    fn id_of_focus_pane(&self) -> Option<String> {
+        None
+    }
+
+    fn enter_session(&mut self, _: &str) -> bool {
+        false
+    }
+
+    fn send_command(&self, command: &str) {
+        self.context.stuff(&format!("{command}\r"));
+    }
+
+    fn list_tabs(&self) -> Vec<TabInfo> {
+        prefix_id_paths(self.context.windows(), "screen")
+    }
+
+    fn list_panes(&self) -> Option<Vec<String>> {
+        None
+    }
+
+    fn change_tab_title(&self, name: &str) {
+        self.context.set_window_title(name);
+    }
+
+    fn change_tab_color(&self, _: kitty::Tabcolor) {}
+}
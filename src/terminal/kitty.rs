@@ -1,12 +1,15 @@
+use crate::terminal::TabInfo;
 use log::debug;
 use palette::{Darken, Srgb, color_difference::Wcag21RelativeContrast};
 use std::env;
 use std::fmt;
+use std::io::Write;
 use std::process::{ChildStdout, Command, Stdio};
 
 #[derive(Debug)]
 pub struct Context {
     value: serde_json::Value,
+    last_window: Option<String>, // id of the window of the last tab created by ktk
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -48,7 +51,11 @@ impl Tabcolor {
         index: usize,
         count: usize,
     ) {
-        let col = gradient.eval_rational(index, count);
+        self.set_color(gradient.eval_rational(index, count), darken);
+    }
+
+    /// Colors of a tab with the background `col` when it is active.
+    pub fn set_color(&mut self, col: colorous::Color, darken: bool) {
         self.active_bg = format!("#{:x}", col).to_string();
         let background: Srgb<f32> = Srgb::new(col.r, col.g, col.b).into_format();
         let foreground: Srgb<f32> = Srgb::new(1.0, 1.0, 1.0).into_format();
@@ -97,6 +104,7 @@ impl Context {
     pub fn new() -> Context {
         Context {
             value: serde_json::from_reader(kittyls()).unwrap(),
+            last_window: None,
         }
     }
 
@@ -135,6 +143,51 @@ impl Context {
         vec
     }
 
+    pub fn tabs(&self) -> Vec<TabInfo> {
+        let mut vec = Vec::new();
+        let mut iow = 0;
+        while self.value[iow].is_object() {
+            let os_window = self.value[iow]["id"].as_i64().unwrap_or(0);
+            let win = self.value[iow]["platform_window_id"]
+                .as_i64()
+                .unwrap_or(os_window);
+            let mut it = 0;
+            while let Some(id) = self.value[iow]["tabs"][it]["id"].as_i64() {
+                vec.push(TabInfo {
+                    id: id.to_string(),
+                    title: self.value[iow]["tabs"][it]["title"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                    session: None,
+                    id_path: IdPath { win, tab: id }.to_string(),
+                });
+                it += 1;
+            }
+            iow += 1;
+        }
+        vec
+    }
+
+    // Ids of the kitty windows of all the tabs, the panes of ktk
+    pub fn panes(&self) -> Vec<String> {
+        let mut vec = Vec::new();
+        let mut iow = 0;
+        while self.value[iow].is_object() {
+            let mut it = 0;
+            while self.value[iow]["tabs"][it].is_object() {
+                let mut iw = 0;
+                while let Some(id) = self.value[iow]["tabs"][it]["windows"][iw]["id"].as_i64() {
+                    vec.push(id.to_string());
+                    iw += 1;
+                }
+                it += 1;
+            }
+            iow += 1;
+        }
+        vec
+    }
+
     #[allow(dead_code)]
     pub fn id_path_of_focus_tab(&self) -> Option<IdPath> {
         let mut iow = 0;
@@ -316,38 +369,106 @@ impl Context {
         self.set_tab_id_color(idtab, tabc)
     }
 
-    pub fn launch_cmd_in_new_tab_name(&mut self, name: &str, opt: &str, env: &str, cmd: &str) {
+    pub fn launch_cmd_in_new_tab_name(&mut self, name: &str, dir: &str, env: &str, cmd: &str) {
         debug!(
-            "launch_cmd_in_new_tab_name name:{:?} opt:{:?} env:{:?} cmd:{:?}",
-            name, opt, env, cmd
+            "launch_cmd_in_new_tab_name name:{:?} dir:{:?} env:{:?} cmd:{:?}",
+            name, dir, env, cmd
         );
-        Command::new("kitty")
+        let mut command = Command::new("kitty");
+        command
             .arg("@")
             .arg("launch")
             .arg("--type=tab")
             .arg("--tab-title")
-            .arg(name)
-            .arg(opt)
-            .arg("--env")
-            .arg(env)
+            .arg(name);
+        if !dir.is_empty() {
+            command.arg("--cwd").arg(dir);
+        }
+        if !env.is_empty() {
+            command.arg("--env").arg(env);
+        }
+        let output = command
             .arg(cmd)
             .output()
             .expect("Failed to launch {cmd} in a new tab");
+        let id = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        self.last_window = (!id.is_empty()).then_some(id);
         self.refresh();
     }
 
-    pub fn launch_shell_in_new_tab_name(&mut self, name: &str) {
+    pub fn launch_shell_in_new_tab_name(&mut self, name: &str, dir: &str, env: &str) {
         debug!("launch_shell_in_new_tab_name {}", name);
         self.launch_cmd_in_new_tab_name(
             name,
-            "",
-            "",
+            dir,
+            env,
             env::var("SHELL")
                 .unwrap_or_else(|_| "/usr/bin/bash".to_string())
                 .as_str(),
         )
     }
 
+    // `kind` is the type of kitty window (window or os-window), the id of
+    // the new window is printed by kitty
+    pub fn launch_shell_in_new_window(
+        &mut self,
+        kind: &str,
+        location: Option<&str>,
+        name: &str,
+        dir: &str,
+        env: &str,
+    ) -> Option<String> {
+        debug!(
+            "launch_shell_in_new_window kind:{:?} location:{:?} name:{:?} dir:{:?} env:{:?}",
+            kind, location, name, dir, env
+        );
+        let mut command = Command::new("kitty");
+        command.arg("@").arg("launch").arg(format!("--type={kind}"));
+        match location {
+            Some(location) => command
+                .arg(format!("--location={location}"))
+                .arg("--window-title")
+                .arg(name),
+            None => command.arg("--tab-title").arg(name),
+        };
+        if !dir.is_empty() {
+            command.arg("--cwd").arg(dir);
+        }
+        if !env.is_empty() {
+            command.arg("--env").arg(env);
+        }
+        let output = command
+            .arg(env::var("SHELL").unwrap_or_else(|_| "/usr/bin/bash".to_string()))
+            .output()
+            .expect("Failed to launch a new kitty window");
+        self.refresh();
+        let id = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        debug!(" new window => {id}");
+        (!id.is_empty()).then_some(id)
+    }
+
+    // The text is read on stdin to be sent as is, without escapes
+    pub fn send_text_to_last_window(&self, text: &str) {
+        debug!("send_text_to_last_window {text:?}");
+        let Some(id) = &self.last_window else {
+            return;
+        };
+        let child = Command::new("kitty")
+            .arg("@")
+            .arg("send-text")
+            .arg("--match")
+            .arg(format!("id:{id}"))
+            .arg("--stdin")
+            .stdin(Stdio::piped())
+            .spawn();
+        if let Ok(mut child) = child {
+            if let Some(mut stdin) = child.stdin.take() {
+                let _ = stdin.write_all(text.as_bytes());
+            }
+            let _ = child.wait();
+        }
+    }
+
     #[allow(dead_code)]
     pub fn focus_tab_id(&self, id: String) {
         debug!("focus_tab_id {id}");
@@ -397,6 +518,7 @@ mod tests {
         let kittyout = BufReader::new(file);
         Context {
             value: serde_json::from_reader(kittyout).unwrap(),
+            last_window: None,
         }
     }
 
@@ -446,6 +568,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_tabs() {
+        let k = new_from_file();
+        let tabs = k.tabs();
+        let titles: Vec<&str> = tabs.iter().map(|t| t.title.as_str()).collect();
+        assert_eq!(titles, vec!["…/rust/ktk/src", "test", "test2", "test3"]);
+        assert_eq!(tabs[3].id_path, "20971556/2");
+    }
+
+    #[test]
+    fn test_panes() {
+        let k = new_from_file();
+        assert_eq!(k.panes(), vec!["1", "6", "7", "8", "2"]);
+    }
+
     #[test]
     fn test_tabs_id() {
         let k = new_from_file();
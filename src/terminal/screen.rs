@@ -0,0 +1,213 @@
+use crate::terminal::TabInfo;
+use log::debug;
+use std::env;
+use std::process::Command;
+
+/// GNU screen, the windows are identified by their number.
+#[derive(Debug)]
+pub struct Context {
+    windows: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Window {
+    pub number: u32,
+    pub flags: String,
+    pub title: String,
+}
+
+fn screen(args: &[&str]) -> String {
+    match Command::new("screen").args(args).output() {
+        Ok(v) => String::from_utf8_lossy(&v.stdout).to_string(),
+        Err(e) => {
+            panic!("Error {e:?}");
+        }
+    }
+}
+
+fn parse_window(chunk: &str) -> Option<Window> {
+    let digits = chunk.chars().take_while(|c| c.is_ascii_digit()).count();
+    let number = chunk[..digits].parse().ok()?;
+    let rest = &chunk[digits..];
+    let (flags, title) = rest.split_once(' ')?;
+    if !flags.chars().all(|c| "-*$!@&Z".contains(c)) {
+        return None;
+    }
+    Some(Window {
+        number,
+        flags: flags.to_string(),
+        title: title.to_string(),
+    })
+}
+
+/// Returns the windows of the output of `screen -Q windows`, like
+/// `0$ bash  1-$ logs  2*$ vim`: the number, the flags (`*` for the
+/// current window) and the title separated by two spaces.
+pub fn parse_windows(output: &str) -> Vec<Window> {
+    let mut windows: Vec<Window> = Vec::new();
+    for chunk in output.trim_end().split("  ") {
+        match (parse_window(chunk), windows.last_mut()) {
+            (Some(w), _) => windows.push(w),
+            // Two spaces in a title
+            (None, Some(last)) => {
+                last.title.push_str("  ");
+                last.title.push_str(chunk);
+            }
+            (None, None) => {}
+        }
+    }
+    windows
+}
+
+impl Context {
+    pub fn new() -> Context {
+        Context {
+            windows: screen(&["-Q", "windows"]),
+        }
+    }
+
+    pub fn refresh(&mut self) {
+        self.windows = screen(&["-Q", "windows"]);
+    }
+
+    pub fn good_term(&self) -> bool {
+        env::var("STY").is_ok()
+    }
+
+    // Returns the session name, like `12345.pts-0.host`
+    pub fn session_name(&self) -> String {
+        env::var("STY").unwrap_or("default".to_string())
+    }
+
+    pub fn id_of_current_window(&self) -> Option<String> {
+        let ret = parse_windows(&self.windows)
+            .into_iter()
+            .find(|w| w.flags.contains('*'))
+            .map(|w| w.number.to_string());
+        debug!("id_of_current_window => {:?}", ret);
+        ret
+    }
+
+    // The shell of a window knows its number, the current window of the
+    // session is only used outside of screen windows
+    pub fn id_path_of_current_window(&self) -> Option<String> {
+        env::var("WINDOW")
+            .ok()
+            .or_else(|| self.id_of_current_window())
+            .map(|id| format!("{}/{}", self.session_name(), id))
+    }
+
+    pub fn windows(&self) -> Vec<TabInfo> {
+        parse_windows(&self.windows)
+            .into_iter()
+            .map(|w| TabInfo {
+                id: w.number.to_string(),
+                id_path: format!("{}/{}", self.session_name(), w.number),
+                title: w.title,
+                session: None,
+            })
+            .collect()
+    }
+
+    pub fn id_of_window_title(&self, title: &str) -> Option<String> {
+        let ret = parse_windows(&self.windows)
+            .into_iter()
+            .find(|w| w.title == title)
+            .map(|w| w.number.to_string());
+        debug!("id_of_window_title {title} => {:?}", ret);
+        ret
+    }
+
+    pub fn select_window_title(&self, title: &str) -> bool {
+        match self.id_of_window_title(title) {
+            Some(id) => {
+                screen(&["-X", "select", &id]);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // screen has no option to set the environment and the directory of a
+    // new window, the shell is started by `env` and `sh`
+    pub fn launch_shell_in_new_window_title(&mut self, title: &str, dir: &str, env: &str) {
+        debug!("launch_shell_in_new_window_title {title} {dir} {env}");
+        let shell = env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
+        let mut args = vec!["-X", "screen", "-t", title];
+        if !env.is_empty() {
+            args.extend(["env", env]);
+        }
+        if !dir.is_empty() {
+            args.extend(["sh", "-c", "cd \"$0\" && exec \"$1\"", dir]);
+        }
+        args.push(&shell);
+        screen(&args);
+        self.refresh();
+    }
+
+    // The text is stuffed in the input of the current window, the new one
+    pub fn stuff(&self, text: &str) {
+        debug!("stuff {text:?}");
+        screen(&["-X", "stuff", text]);
+    }
+
+    pub fn set_window_title(&self, title: &str) {
+        debug!("set_window_title {title}");
+        match env::var("WINDOW") {
+            Ok(id) => screen(&["-p", &id, "-X", "title", title]),
+            Err(_) => screen(&["-X", "title", title]),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Context, Window, parse_windows};
+    use std::fs;
+
+    fn new_from_file(file: &str) -> Context {
+        Context {
+            windows: fs::read_to_string(file).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_parse_windows() {
+        let k = new_from_file("./tests/screen-windows.txt");
+        let windows = parse_windows(&k.windows);
+        assert_eq!(windows.len(), 4);
+        assert_eq!(
+            windows[2],
+            Window {
+                number: 2,
+                flags: "*$".to_string(),
+                title: "☸>>default::dev".to_string()
+            }
+        );
+        assert_eq!(windows[3].title, "vim notes.md");
+        assert_eq!(parse_windows("0*$ a  b")[0].title, "a  b");
+    }
+
+    #[test]
+    fn test_id_of_current_window() {
+        let k = new_from_file("./tests/screen-windows.txt");
+        assert_eq!(k.id_of_current_window(), Some("2".to_string()));
+        let k = new_from_file("./tests/screen-windows-no-current.txt");
+        assert_eq!(k.id_of_current_window(), None);
+    }
+
+    #[test]
+    fn test_id_of_window_title() {
+        let k = new_from_file("./tests/screen-windows.txt");
+        assert_eq!(
+            k.id_of_window_title("☸>>kube-system::prod"),
+            Some("1".to_string())
+        );
+        assert_eq!(k.id_of_window_title("☸>>kube-system"), None);
+        let k = new_from_file("./tests/screen-windows-no-current.txt");
+        assert_eq!(
+            k.id_of_window_title("☸>>web::staging"),
+            Some("7".to_string())
+        );
+    }
+}
@@ -1,14 +1,77 @@
+use crate::terminal::TabInfo;
+use crate::terminal::kitty::Tabcolor;
 use log::debug;
 use std::env;
 use std::process::Command;
 
 #[derive(Debug)]
-pub struct Context {}
+pub struct Context {
+    last_window: Option<String>,    // id of the last window created by ktk
+    session: Option<String>,        // session of the new windows, the current one if None
+    initial_window: Option<String>, // window of a session created by ktk, replaced by its first tab
+}
+
+fn tmux(args: &[&str]) -> String {
+    match Command::new("tmux").args(args).output() {
+        Ok(v) => String::from_utf8_lossy(&v.stdout).trim().to_string(),
+        Err(e) => {
+            panic!("Error {e:?}");
+        }
+    }
+}
+
+/// Returns the windows of the output of `tmux list-windows -a` with the
+/// format of `Context::windows`.
+pub fn parse_windows(output: &str) -> Vec<TabInfo> {
+    output
+        .lines()
+        .filter_map(|l| {
+            let mut fields = l.splitn(3, '\t');
+            let id = fields.next()?.to_string();
+            let session = fields.next()?.to_string();
+            Some(TabInfo {
+                id_path: format!("{session}/{id}"),
+                id,
+                session: Some(session),
+                title: fields.next()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+// tmux has no NONE color, the default style is used instead
+fn style_color(color: &str) -> &str {
+    match color {
+        "NONE" | "" => "default",
+        c => c,
+    }
+}
+
+/// Returns the `window-status-style` and `window-status-current-style` of
+/// a window with the colors of the tab.
+pub fn window_styles(tab: &Tabcolor) -> (String, String) {
+    (
+        format!(
+            "fg={},bg={}",
+            style_color(&tab.inactive_fg),
+            style_color(&tab.inactive_bg)
+        ),
+        format!(
+            "fg={},bg={}",
+            style_color(&tab.active_fg),
+            style_color(&tab.active_bg)
+        ),
+    )
+}
 
 impl Context {
     #[allow(dead_code)]
     pub fn new() -> Context {
-        Context {}
+        Context {
+            last_window: None,
+            session: None,
+            initial_window: None,
+        }
     }
 
     #[allow(dead_code)]
@@ -21,6 +84,9 @@ impl Context {
 
     #[allow(dead_code)]
     pub fn current_session(&self) -> String {
+        if let Some(session) = &self.session {
+            return session.clone();
+        }
         match Command::new("tmux")
             .arg("display-message")
             .arg("-p")
@@ -67,8 +133,12 @@ impl Context {
 
     pub fn id_of_window_name(&self, name: &str) -> Option<String> {
         debug!("id_of_window_name {name}");
-        match Command::new("tmux")
-            .arg("list-windows")
+        let mut command = Command::new("tmux");
+        command.arg("list-windows");
+        if let Some(session) = &self.session {
+            command.arg("-t").arg(format!("={session}"));
+        }
+        match command
             .arg("-F")
             .arg("#{window_id}")
             .arg("-f")
@@ -105,36 +175,156 @@ impl Context {
         }
     }
 
-    pub fn launch_cmd_in_new_tab_name(&self, name: &str, dir: &str, env: &str, cmd: &str) {
+    pub fn launch_cmd_in_new_tab_name(&mut self, name: &str, dir: &str, env: &str, cmd: &str) {
         debug!(
             "launch_cmd_in_new_tab_name name:{:?} dir:{:?} env:{:?} cmd:{:?}",
             name, dir, env, cmd
         );
-        Command::new("tmux")
+        let mut command = Command::new("tmux");
+        command
             .arg("new-window")
+            .arg("-P")
+            .arg("-F")
+            .arg("#{window_id}")
             .arg("-n")
-            .arg(name)
-            .arg("-e")
-            .arg(env)
-            .arg("-c")
-            .arg(dir)
+            .arg(name);
+        // After the current window of the session, which is the last one
+        // created, to keep the order of the tabs of a session
+        if let Some(session) = &self.session {
+            command.arg("-a").arg("-t").arg(format!("={session}:"));
+        }
+        if !env.is_empty() {
+            command.arg("-e").arg(env);
+        }
+        if !dir.is_empty() {
+            command.arg("-c").arg(dir);
+        }
+        let output = command
             .arg(cmd)
             .output()
             .expect("Failed to launch tmux window");
+        let id = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        debug!(" new window => {id}");
+        self.last_window = (!id.is_empty()).then_some(id);
+        if let Some(initial) = self.initial_window.take() {
+            tmux(&["kill-window", "-t", &initial]);
+        }
     }
 
-    pub fn launch_shell_in_new_tab_name(&self, name: &str) {
+    pub fn launch_shell_in_new_tab_name(&mut self, name: &str, dir: &str, env: &str) {
         debug!("launch_shell_in_new_tab_name {name}");
         self.launch_cmd_in_new_tab_name(
             name,
-            "",
-            "",
+            dir,
+            env,
             env::var("SHELL")
                 .unwrap_or_else(|_| "/usr/bin/bash".to_string())
                 .as_str(),
         )
     }
 
+    // `direction` is the option of split-window, -h for side by side
+    // panes and -v for stacked panes
+    pub fn launch_shell_in_new_pane(
+        &mut self,
+        direction: &str,
+        dir: &str,
+        env: &str,
+    ) -> Option<String> {
+        debug!(
+            "launch_shell_in_new_pane direction:{:?} dir:{:?} env:{:?}",
+            direction, dir, env
+        );
+        let mut command = Command::new("tmux");
+        command
+            .arg("split-window")
+            .arg(direction)
+            .arg("-P")
+            .arg("-F")
+            .arg("#{pane_id}");
+        // Split the pane running ktk, not the active pane of the session
+        if let Ok(pane) = env::var("TMUX_PANE") {
+            command.arg("-t").arg(pane);
+        }
+        if !env.is_empty() {
+            command.arg("-e").arg(env);
+        }
+        if !dir.is_empty() {
+            command.arg("-c").arg(dir);
+        }
+        let output = command
+            .arg(env::var("SHELL").unwrap_or_else(|_| "/usr/bin/bash".to_string()))
+            .output()
+            .expect("Failed to split tmux window");
+        let id = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        debug!(" new pane => {id}");
+        (!id.is_empty()).then_some(id)
+    }
+
+    pub fn windows(&self) -> Vec<TabInfo> {
+        parse_windows(&tmux(&[
+            "list-windows",
+            "-a",
+            "-F",
+            "#{window_id}\t#{session_name}\t#{window_name}",
+        ]))
+    }
+
+    pub fn panes(&self) -> Vec<String> {
+        tmux(&["list-panes", "-a", "-F", "#{pane_id}"])
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    // The session is created if needed and the client switches to it
+    pub fn enter_session(&mut self, name: &str) {
+        debug!("enter_session {name}");
+        let target = format!("={name}");
+        let exists = Command::new("tmux")
+            .arg("has-session")
+            .arg("-t")
+            .arg(&target)
+            .output()
+            .is_ok_and(|o| o.status.success());
+        if !exists {
+            let id = tmux(&["new-session", "-d", "-s", name, "-P", "-F", "#{window_id}"]);
+            self.initial_window = (!id.is_empty()).then_some(id);
+        }
+        if env::var("TMUX").is_ok() {
+            tmux(&["switch-client", "-t", &target]);
+        }
+        self.session = Some(name.to_string());
+    }
+
+    pub fn send_keys_to_last_window(&self, keys: &str) {
+        debug!("send_keys_to_last_window {keys:?}");
+        if let Some(id) = &self.last_window {
+            tmux(&["send-keys", "-t", id, keys, "Enter"]);
+        }
+    }
+
+    // Without a target, tmux uses the window of the pane running ktk,
+    // not the window it has just created
+    pub fn set_tab_color(&self, tab: &Tabcolor) {
+        debug!("set_tab_color {:?}", tab);
+        let (style, current_style) = window_styles(tab);
+        for (option, value) in [
+            ("window-status-style", style),
+            ("window-status-current-style", current_style),
+        ] {
+            let mut cmd = Command::new("tmux");
+            cmd.arg("set-window-option");
+            if let Some(id) = &self.last_window {
+                cmd.arg("-t").arg(id);
+            }
+            cmd.arg(option)
+                .arg(value)
+                .output()
+                .expect("Failed to change tmux window style");
+        }
+    }
+
     pub fn set_tab_title(&self, name: &str) {
         debug!("set_tab_title {name}");
         Command::new("tmux")
@@ -144,3 +334,39 @@ impl Context {
             .expect("Failed to launch tmux window");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_windows, window_styles};
+    use crate::terminal::kitty::Tabcolor;
+    use std::fs;
+
+    #[test]
+    fn test_parse_windows() {
+        let output = fs::read_to_string("./tests/tmux-list-windows.txt").unwrap();
+        let windows = parse_windows(&output);
+        assert_eq!(windows.len(), 3);
+        assert_eq!(windows[1].id, "@3");
+        assert_eq!(windows[1].title, "☸>>kube-system::prod");
+        assert_eq!(windows[1].id_path, "main/@3");
+        assert_eq!(windows[2].session.as_deref(), Some("ops"));
+        assert_eq!(windows[2].title, "name\twith tab");
+    }
+
+    #[test]
+    fn test_window_styles() {
+        let tab = Tabcolor {
+            active_bg: "#23171b".to_string(),
+            inactive_bg: "NONE".to_string(),
+            active_fg: "#FFFFFF".to_string(),
+            inactive_fg: "#23171b".to_string(),
+        };
+        assert_eq!(
+            window_styles(&tab),
+            (
+                "fg=#23171b,bg=default".to_string(),
+                "fg=#FFFFFF,bg=#23171b".to_string()
+            )
+        );
+    }
+}
@@ -1,12 +1,44 @@
+use crate::terminal::TabInfo;
+use crate::terminal::kitty::Tabcolor;
+use base64::{Engine, engine::general_purpose::STANDARD};
 use simplelog::debug;
 use std::env;
 use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
 use std::process::{ChildStdout, Command, Stdio};
 
+/// Lua handler of wezterm coloring the tabs with the user variables set by ktk.
+pub const TAB_TITLE_LUA: &str = include_str!("../../conf/wezterm-ktk.lua");
+
 #[derive(Debug)]
 pub struct Context {
     value: serde_json::Value,
     client: serde_json::Value,
+    last_pane: Option<i64>,    // id of the pane of the last tab created by ktk
+    workspace: Option<String>, // workspace of the new tabs, the active one if None
+}
+
+// Normalized name of a workspace, used as a directory name
+fn workspace_dir(workspace: &str) -> String {
+    // replace emoji and space by underscore
+    let wsanitize = workspace
+        .chars()
+        .map(|x| match x.is_alphanumeric() {
+            true => x,
+            false => '_',
+        })
+        .collect::<String>();
+    let workspace = wsanitize.trim_matches('_');
+    if workspace.is_empty() {
+        return "default".to_string();
+    }
+    workspace.to_string()
+}
+
+/// Returns the OSC 1337 escape sequence setting a user variable of a pane.
+pub fn user_var_sequence(name: &str, value: &str) -> String {
+    format!("\x1b]1337;SetUserVar={name}={}\x07", STANDARD.encode(value))
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -52,6 +84,8 @@ impl Context {
         Context {
             value: serde_json::from_reader(weztermls()).unwrap(),
             client: serde_json::from_reader(weztermlsclient()).unwrap(),
+            last_pane: None,
+            workspace: None,
         }
     }
 
@@ -62,6 +96,9 @@ impl Context {
 
     // Returns the name of the active workspace.
     pub fn active_workspace(&self) -> String {
+        if let Some(workspace) = &self.workspace {
+            return workspace.clone();
+        }
         let pane_id = self.client[0]["focused_pane_id"].as_i64();
         debug!("id_of_focus_pane => {:?}", pane_id);
         let mut it = 0;
@@ -79,29 +116,53 @@ impl Context {
 
     // Returns the normalize name of the active workspace.
     pub fn platform_window_id(&self) -> String {
-        let pane_id = self.client[0]["focused_pane_id"].as_i64();
-        debug!("id_of_focus_pane => {:?}", pane_id);
+        workspace_dir(&self.active_workspace())
+    }
+
+    // Returns the id of a window of the workspace.
+    pub fn window_of_workspace(&self, workspace: &str) -> Option<i64> {
         let mut it = 0;
         while self.value[it].is_object() {
-            if self.value[it]["pane_id"].as_i64().or(None) == pane_id {
-                let w = self.value[it]["workspace"].to_string();
-                // replace emoji and space by underscore
-                let wsanitize = w
-                    .chars()
-                    .map(|x| match x.is_alphanumeric() {
-                        true => x,
-                        false => '_',
-                    })
-                    .collect::<String>();
-                let workspace = wsanitize.trim_matches('_');
-                if workspace.is_empty() {
-                    return "default".to_string();
-                }
-                return workspace.to_string();
+            if self.value[it]["workspace"].as_str() == Some(workspace) {
+                return self.value[it]["window_id"].as_i64();
             }
             it += 1;
         }
-        "default".to_string()
+        None
+    }
+
+    // A tab is listed once per pane
+    pub fn tabs(&self) -> Vec<TabInfo> {
+        let mut vec: Vec<TabInfo> = Vec::new();
+        let mut it = 0;
+        while self.value[it].is_object() {
+            let pane = &self.value[it];
+            let id = pane["tab_id"].to_string();
+            if !vec.iter().any(|t| t.id == id) {
+                let workspace = pane["workspace"].as_str().map(String::from);
+                vec.push(TabInfo {
+                    id_path: format!(
+                        "{}/{id}",
+                        workspace_dir(workspace.as_deref().unwrap_or_default())
+                    ),
+                    id,
+                    title: pane["tab_title"].as_str().unwrap_or_default().to_string(),
+                    session: workspace,
+                });
+            }
+            it += 1;
+        }
+        vec
+    }
+
+    pub fn panes(&self) -> Vec<String> {
+        let mut vec = Vec::new();
+        let mut it = 0;
+        while let Some(id) = self.value[it]["pane_id"].as_i64() {
+            vec.push(id.to_string());
+            it += 1;
+        }
+        vec
     }
 
     #[allow(dead_code)]
@@ -231,22 +292,36 @@ impl Context {
             .expect("Failed to set tab title");
     }
 
-    pub fn launch_cmd_in_new_tab_name(&mut self, name: &str, opt: &str, env: &str, cmd: &str) {
+    pub fn launch_cmd_in_new_tab_name(&mut self, name: &str, dir: &str, env: &str, cmd: &str) {
         debug!(
-            "launch_cmd_in_new_tab_name name:{:?} opt:{:?} env:{:?} cmd:{:?}",
-            name, opt, env, cmd
+            "launch_cmd_in_new_tab_name name:{:?} dir:{:?} env:{:?} cmd:{:?}",
+            name, dir, env, cmd
         );
-        let output = Command::new("wezterm")
-            .arg("cli")
-            .arg("spawn")
-            .arg("--")
-            .arg(cmd)
-            .output()
-            .expect("failed");
+        let mut command = Command::new("wezterm");
+        command.arg("cli").arg("spawn");
+        if let Some(workspace) = &self.workspace {
+            match self.window_of_workspace(workspace) {
+                Some(id) => command.arg(format!("--window-id={id}")),
+                None => command
+                    .arg("--new-window")
+                    .arg("--workspace")
+                    .arg(workspace),
+            };
+        }
+        if !dir.is_empty() {
+            command.arg("--cwd").arg(dir);
+        }
+        command.arg("--");
+        // wezterm cli spawn has no option to set the environment
+        if !env.is_empty() {
+            command.arg("env").arg(env);
+        }
+        let output = command.arg(cmd).output().expect("failed");
         let pane_id = String::from_utf8_lossy(&output.stdout)
             .to_string()
             .trim_end()
             .to_string();
+        self.last_pane = pane_id.parse().ok();
         let opt = format!("--pane-id={}", pane_id);
         debug!("Execute => wezterm cli set-tab-title '{name}' {opt}");
         Command::new("wezterm")
@@ -263,18 +338,71 @@ impl Context {
         self.refresh();
     }
 
-    pub fn launch_shell_in_new_tab_name(&mut self, name: &str) {
+    pub fn launch_shell_in_new_tab_name(&mut self, name: &str, dir: &str, env: &str) {
         debug!("launch_shell_in_new_tab_name => {}", name);
         self.launch_cmd_in_new_tab_name(
             name,
-            "",
-            "",
+            dir,
+            env,
             env::var("SHELL")
                 .unwrap_or_else(|_| "/usr/bin/bash".to_string())
                 .as_str(),
         );
     }
 
+    // `args` are the options of the wezterm cli subcommand creating the
+    // pane, split-pane or spawn, which print the id of the new pane
+    pub fn launch_shell_in_new_pane(
+        &mut self,
+        args: &[&str],
+        dir: &str,
+        env: &str,
+    ) -> Option<String> {
+        debug!(
+            "launch_shell_in_new_pane args:{:?} dir:{:?} env:{:?}",
+            args, dir, env
+        );
+        let mut command = Command::new("wezterm");
+        command.arg("cli").args(args);
+        if !dir.is_empty() {
+            command.arg("--cwd").arg(dir);
+        }
+        command.arg("--");
+        if !env.is_empty() {
+            command.arg("env").arg(env);
+        }
+        let output = command
+            .arg(env::var("SHELL").unwrap_or_else(|_| "/usr/bin/bash".to_string()))
+            .output()
+            .expect("Failed to create a wezterm pane");
+        let pane_id = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        debug!(" new pane => {pane_id}");
+        self.last_pane = pane_id.parse().ok();
+        self.refresh();
+        (!pane_id.is_empty()).then_some(pane_id)
+    }
+
+    // wezterm cli can't switch the workspace of the gui, the tabs of a
+    // new workspace are opened in a new window
+    pub fn enter_workspace(&mut self, name: &str) {
+        debug!("enter_workspace {name}");
+        self.workspace = Some(name.to_string());
+    }
+
+    pub fn send_text_to_last_pane(&self, text: &str) {
+        debug!("send_text_to_last_pane {text:?}");
+        if let Some(id) = self.last_pane {
+            Command::new("wezterm")
+                .arg("cli")
+                .arg("send-text")
+                .arg(format!("--pane-id={id}"))
+                .arg("--no-paste")
+                .arg(text)
+                .output()
+                .expect("Failed to send text");
+        }
+    }
+
     #[allow(dead_code)]
     pub fn focus_tab_id(&self, id: String) {
         debug!("focus_tab_id => {id}");
@@ -297,6 +425,51 @@ impl Context {
         self.refresh();
     }
 
+    pub fn tty_of_pane(&self, pane_id: i64) -> Option<String> {
+        let mut it = 0;
+        while self.value[it].is_object() {
+            if self.value[it]["pane_id"].as_i64() == Some(pane_id) {
+                return self.value[it]["tty_name"].as_str().map(String::from);
+            }
+            it += 1;
+        }
+        None
+    }
+
+    // The pane of the tab created by ktk, otherwise the pane running ktk
+    fn target_pane(&self) -> Option<i64> {
+        self.last_pane
+            .or_else(|| env::var("WEZTERM_PANE").ok()?.parse().ok())
+            .or_else(|| self.client[0]["focused_pane_id"].as_i64())
+    }
+
+    // wezterm has no cli command to color a tab, the colors are published
+    // in user variables of the pane with escape sequences written on its
+    // tty, and used by the format-tab-title handler of `ktk wezterm-lua`
+    pub fn set_tab_color(&self, tab: &Tabcolor) {
+        debug!("set_tab_color {:?}", tab);
+        let Some(tty) = self.target_pane().and_then(|id| self.tty_of_pane(id)) else {
+            debug!("no tty for the pane of the tab");
+            return;
+        };
+        let sequences: String = [
+            ("ktk_active_bg", &tab.active_bg),
+            ("ktk_active_fg", &tab.active_fg),
+            ("ktk_inactive_bg", &tab.inactive_bg),
+            ("ktk_inactive_fg", &tab.inactive_fg),
+        ]
+        .iter()
+        .map(|(name, value)| user_var_sequence(name, value))
+        .collect();
+        let written = OpenOptions::new()
+            .write(true)
+            .open(&tty)
+            .and_then(|mut f| f.write_all(sequences.as_bytes()));
+        if let Err(e) = written {
+            debug!("Failed to write tab colors on {tty}: {e}");
+        }
+    }
+
     #[allow(dead_code)]
     pub fn focus_pane_id(&self, id: i64) {
         debug!("focus_pane_id => {id}");
@@ -311,7 +484,7 @@ impl Context {
 
 #[cfg(test)]
 mod tests {
-    use super::Context;
+    use super::{Context, user_var_sequence};
     use crate::PathBuf;
     use crate::io::*;
     use std::fs::File;
@@ -326,9 +499,45 @@ mod tests {
         Context {
             value: serde_json::from_reader(weztermout).unwrap(),
             client: serde_json::from_reader(weztermcliout).unwrap(),
+            last_pane: None,
+            workspace: None,
         }
     }
 
+    #[test]
+    fn test_tabs() {
+        let k = new_from_file();
+        let tabs = k.tabs();
+        assert_eq!(tabs.len(), 8);
+        assert_eq!(tabs[1].id, "4");
+        assert_eq!(tabs[1].title, "test");
+        assert_eq!(tabs[1].session.as_deref(), Some("default"));
+        assert_eq!(tabs[1].id_path, "default/4");
+    }
+
+    #[test]
+    fn test_panes() {
+        let k = new_from_file();
+        let panes = k.panes();
+        assert_eq!(panes.len(), 13);
+        assert_eq!(panes[1], "19");
+    }
+
+    #[test]
+    fn test_tty_of_pane() {
+        let k = new_from_file();
+        assert_eq!(k.tty_of_pane(0), Some("/dev/pts/0".to_string()));
+        assert_eq!(k.tty_of_pane(999), None);
+    }
+
+    #[test]
+    fn test_user_var_sequence() {
+        assert_eq!(
+            user_var_sequence("ktk_active_bg", "#23171b"),
+            "\x1b]1337;SetUserVar=ktk_active_bg=IzIzMTcxYg==\x07"
+        );
+    }
+
     #[test]
     fn test_id_of_focus_pane() {
         let k = new_from_file();
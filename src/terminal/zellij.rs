@@ -0,0 +1,239 @@
+use crate::terminal::{TabInfo, file_id};
+use log::debug;
+use std::env;
+use std::process::Command;
+
+/// Zellij has no identifier for the tabs in its cli, a tab is identified by
+/// its name, ktk gives a unique name to each of its tabs.
+#[derive(Debug)]
+pub struct Context {
+    layout: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tab {
+    pub name: String,
+    pub focus: bool,
+}
+
+fn zellij_action(args: &[&str]) -> String {
+    match Command::new("zellij").arg("action").args(args).output() {
+        Ok(v) => String::from_utf8_lossy(&v.stdout).to_string(),
+        Err(e) => {
+            panic!("Error {e:?}");
+        }
+    }
+}
+
+// Value of a `key="value"` property in a kdl node
+fn kdl_string(line: &str, key: &str) -> Option<String> {
+    let start = line.find(&format!("{key}=\""))? + key.len() + 2;
+    let mut value = String::new();
+    let mut chars = line[start..].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => value.push(chars.next()?),
+            '"' => return Some(value),
+            _ => value.push(c),
+        }
+    }
+    None
+}
+
+/// Returns the tabs of the output of `zellij action dump-layout`, only the
+/// tabs at the top of the layout are taken, not the ones of the swap layouts.
+pub fn tabs_of_layout(layout: &str) -> Vec<Tab> {
+    // The indentation of the layout is the one of its first nested line
+    let indent = layout
+        .lines()
+        .find_map(|l| {
+            let node = l.trim_start();
+            (!node.is_empty() && node.len() < l.len()).then(|| &l[..l.len() - node.len()])
+        })
+        .unwrap_or("    ");
+    layout
+        .lines()
+        .filter(|l| {
+            l.strip_prefix(indent)
+                .is_some_and(|n| n.starts_with("tab "))
+        })
+        .map(|l| Tab {
+            name: kdl_string(l, "name").unwrap_or_default(),
+            focus: l.contains(" focus=true"),
+        })
+        .collect()
+}
+
+/// Returns the names of the output of `zellij action query-tab-names`.
+pub fn tab_names(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+// Identifier of a tab usable as a file name in a shell command
+pub fn tab_id(name: &str) -> String {
+    file_id(name)
+}
+
+impl Context {
+    pub fn new() -> Context {
+        Context {
+            layout: zellij_action(&["dump-layout"]),
+        }
+    }
+
+    pub fn refresh(&mut self) {
+        self.layout = zellij_action(&["dump-layout"]);
+    }
+
+    pub fn good_term(&self) -> bool {
+        env::var("ZELLIJ").is_ok()
+    }
+
+    pub fn session_name(&self) -> String {
+        env::var("ZELLIJ_SESSION_NAME").unwrap_or("default".to_string())
+    }
+
+    pub fn name_of_focus_tab(&self) -> Option<String> {
+        let ret = tabs_of_layout(&self.layout)
+            .into_iter()
+            .find(|t| t.focus)
+            .map(|t| t.name);
+        debug!("name_of_focus_tab => {:?}", ret);
+        ret
+    }
+
+    pub fn id_of_focus_tab(&self) -> Option<String> {
+        self.name_of_focus_tab().map(|name| tab_id(&name))
+    }
+
+    pub fn id_of_tab_name(&self, name: &str) -> Option<String> {
+        debug!("id_of_tab_name {name}");
+        tab_names(&zellij_action(&["query-tab-names"]))
+            .iter()
+            .find(|n| *n == name)
+            .map(|n| tab_id(n))
+    }
+
+    pub fn tabs(&self) -> Vec<TabInfo> {
+        tabs_of_layout(&self.layout)
+            .into_iter()
+            .map(|t| TabInfo {
+                id: tab_id(&t.name),
+                id_path: format!("{}/{}", self.session_name(), tab_id(&t.name)),
+                title: t.name,
+                session: None,
+            })
+            .collect()
+    }
+
+    pub fn id_path_of_focus_tab(&self) -> Option<String> {
+        self.id_of_focus_tab()
+            .map(|id| format!("{}/{}", self.session_name(), id))
+    }
+
+    pub fn go_to_tab_name(&self, name: &str) -> bool {
+        debug!("go_to_tab_name {name}");
+        if self.id_of_tab_name(name).is_none() {
+            return false;
+        }
+        zellij_action(&["go-to-tab-name", name]);
+        true
+    }
+
+    // The shell of a new tab is started by the zellij server, its
+    // environment can't be given, only its working directory
+    pub fn launch_shell_in_new_tab_name(&mut self, name: &str, dir: &str) {
+        debug!("launch_shell_in_new_tab_name {name} {dir}");
+        if dir.is_empty() {
+            zellij_action(&["new-tab", "--name", name]);
+        } else {
+            zellij_action(&["new-tab", "--name", name, "--cwd", dir]);
+        }
+        self.refresh();
+    }
+
+    // The chars are written in the focused pane, the one of the new tab
+    pub fn write_chars(&self, text: &str) {
+        debug!("write_chars {text:?}");
+        zellij_action(&["write-chars", text]);
+    }
+
+    pub fn set_tab_title(&self, name: &str) {
+        debug!("set_tab_title {name}");
+        zellij_action(&["rename-tab", name]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Context, Tab, tab_id, tab_names, tabs_of_layout};
+    use std::fs;
+
+    fn new_from_file() -> Context {
+        Context {
+            layout: fs::read_to_string("./tests/zellij-dump-layout.kdl").unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_tabs_of_layout() {
+        let k = new_from_file();
+        assert_eq!(
+            tabs_of_layout(&k.layout),
+            vec![
+                Tab {
+                    name: "Tab #1".to_string(),
+                    focus: false
+                },
+                Tab {
+                    name: "☸>>kube-system::prod".to_string(),
+                    focus: true
+                },
+                Tab {
+                    name: "say \"hello\"".to_string(),
+                    focus: false
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_id_of_focus_tab() {
+        let k = new_from_file();
+        assert_eq!(
+            k.name_of_focus_tab(),
+            Some("☸>>kube-system::prod".to_string())
+        );
+        assert_eq!(
+            k.id_of_focus_tab(),
+            Some("%E2%98%B8%3E%3Ekube-system%3A%3Aprod".to_string())
+        );
+    }
+
+    #[test]
+    fn test_tab_names() {
+        let output = fs::read_to_string("./tests/zellij-query-tab-names.txt").unwrap();
+        assert_eq!(
+            tab_names(&output),
+            vec!["Tab #1", "☸>>kube-system::prod", "say \"hello\""]
+        );
+        assert_eq!(tab_id("say \"hello\""), "say%20%22hello%22");
+        assert_ne!(tab_id("a b"), tab_id("a_b"));
+    }
+
+    #[test]
+    fn test_tabs_of_layout_indent() {
+        let layout = "layout {\n  cwd \"/home/user\"\n  tab name=\"dev\" focus=true {\n    pane\n  }\n  swap_tiled_layout name=\"vertical\" {\n    tab max_panes=5 {\n    }\n  }\n}\n";
+        assert_eq!(
+            tabs_of_layout(layout),
+            vec![Tab {
+                name: "dev".to_string(),
+                focus: true
+            }]
+        );
+    }
+}